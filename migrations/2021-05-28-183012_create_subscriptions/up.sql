@@ -0,0 +1,6 @@
+CREATE TABLE subscriptions (
+  id TEXT PRIMARY KEY,
+  url TEXT,
+  created_at TEXT,
+  modified_at TEXT
+);
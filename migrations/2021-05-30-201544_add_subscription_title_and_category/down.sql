@@ -0,0 +1,15 @@
+CREATE TABLE tmp_subscriptions (
+  id TEXT PRIMARY KEY,
+  url TEXT,
+  created_at TEXT,
+  modified_at TEXT
+);
+INSERT INTO tmp_subscriptions
+SELECT id,
+  url,
+  created_at,
+  modified_at
+FROM subscriptions;
+DROP TABLE IF EXISTS subscriptions;
+ALTER TABLE tmp_subscriptions
+  RENAME TO subscriptions;
@@ -0,0 +1,4 @@
+ALTER TABLE subscriptions
+ADD COLUMN title TEXT;
+ALTER TABLE subscriptions
+ADD COLUMN category TEXT;
@@ -0,0 +1,20 @@
+DROP TABLE subscription_sources;
+CREATE TABLE tmp_subscriptions (
+  id TEXT PRIMARY KEY,
+  url TEXT,
+  created_at TEXT,
+  modified_at TEXT,
+  title TEXT,
+  category TEXT
+);
+INSERT INTO tmp_subscriptions
+SELECT id,
+  url,
+  created_at,
+  modified_at,
+  title,
+  category
+FROM subscriptions;
+DROP TABLE IF EXISTS subscriptions;
+ALTER TABLE tmp_subscriptions
+  RENAME TO subscriptions;
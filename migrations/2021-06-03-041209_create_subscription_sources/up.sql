@@ -0,0 +1,10 @@
+CREATE TABLE subscription_sources (
+  id TEXT PRIMARY KEY,
+  url TEXT,
+  created_at TEXT,
+  modified_at TEXT
+);
+ALTER TABLE subscriptions
+ADD COLUMN source_id TEXT;
+ALTER TABLE subscriptions
+ADD COLUMN active BOOLEAN DEFAULT 1;
@@ -0,0 +1,23 @@
+CREATE TABLE tmp_subscriptions (
+  id TEXT PRIMARY KEY,
+  url TEXT,
+  created_at TEXT,
+  modified_at TEXT,
+  title TEXT,
+  category TEXT,
+  source_id TEXT,
+  active BOOLEAN DEFAULT 1
+);
+INSERT INTO tmp_subscriptions
+SELECT id,
+  url,
+  created_at,
+  modified_at,
+  title,
+  category,
+  source_id,
+  active
+FROM subscriptions;
+DROP TABLE IF EXISTS subscriptions;
+ALTER TABLE tmp_subscriptions
+  RENAME TO subscriptions;
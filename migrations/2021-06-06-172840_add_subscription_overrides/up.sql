@@ -0,0 +1,8 @@
+ALTER TABLE subscriptions
+ADD COLUMN request_timeout INTEGER;
+ALTER TABLE subscriptions
+ADD COLUMN min_fetch_period INTEGER;
+ALTER TABLE subscriptions
+ADD COLUMN retain_src BOOLEAN;
+ALTER TABLE subscriptions
+ADD COLUMN skip_entry_update BOOLEAN;
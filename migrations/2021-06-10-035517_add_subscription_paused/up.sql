@@ -0,0 +1,6 @@
+ALTER TABLE subscriptions
+ADD COLUMN paused_at TEXT;
+ALTER TABLE subscriptions
+ADD COLUMN paused_reason TEXT;
+ALTER TABLE subscriptions
+ADD COLUMN resumed_at TEXT;
@@ -0,0 +1,2 @@
+ALTER TABLE subscriptions
+ADD COLUMN max_fetch_period INTEGER;
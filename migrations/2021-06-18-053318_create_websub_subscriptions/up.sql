@@ -0,0 +1,11 @@
+CREATE TABLE websub_subscriptions (
+  id TEXT PRIMARY KEY,
+  feed_url TEXT,
+  hub_url TEXT,
+  topic_url TEXT,
+  secret TEXT,
+  state TEXT,
+  lease_expires_at TEXT,
+  created_at TEXT,
+  modified_at TEXT
+);
@@ -0,0 +1 @@
+DROP TABLE rsscloud_registrations;
@@ -0,0 +1,8 @@
+CREATE TABLE rsscloud_registrations (
+  id TEXT PRIMARY KEY,
+  feed_url TEXT,
+  cloud_url TEXT,
+  registered_at TEXT,
+  created_at TEXT,
+  modified_at TEXT
+);
@@ -0,0 +1,39 @@
+CREATE TABLE tmp_subscriptions (
+  id TEXT PRIMARY KEY,
+  url TEXT,
+  created_at TEXT,
+  modified_at TEXT,
+  title TEXT,
+  category TEXT,
+  source_id TEXT,
+  active BOOLEAN DEFAULT 1,
+  request_timeout INTEGER,
+  min_fetch_period INTEGER,
+  retain_src BOOLEAN,
+  skip_entry_update BOOLEAN,
+  paused_at TEXT,
+  paused_reason TEXT,
+  resumed_at TEXT,
+  max_fetch_period INTEGER
+);
+INSERT INTO tmp_subscriptions
+SELECT id,
+  url,
+  created_at,
+  modified_at,
+  title,
+  category,
+  source_id,
+  active,
+  request_timeout,
+  min_fetch_period,
+  retain_src,
+  skip_entry_update,
+  paused_at,
+  paused_reason,
+  resumed_at,
+  max_fetch_period
+FROM subscriptions;
+DROP TABLE IF EXISTS subscriptions;
+ALTER TABLE tmp_subscriptions
+  RENAME TO subscriptions;
@@ -0,0 +1,2 @@
+ALTER TABLE feed_history
+ADD COLUMN body_hash TEXT;
@@ -0,0 +1,25 @@
+CREATE TABLE tmp_websub_subscriptions (
+  id TEXT PRIMARY KEY,
+  feed_url TEXT,
+  hub_url TEXT,
+  topic_url TEXT,
+  secret TEXT,
+  state TEXT,
+  lease_expires_at TEXT,
+  created_at TEXT,
+  modified_at TEXT
+);
+INSERT INTO tmp_websub_subscriptions
+SELECT id,
+  feed_url,
+  hub_url,
+  topic_url,
+  secret,
+  state,
+  lease_expires_at,
+  created_at,
+  modified_at
+FROM websub_subscriptions;
+DROP TABLE IF EXISTS websub_subscriptions;
+ALTER TABLE tmp_websub_subscriptions
+  RENAME TO websub_subscriptions;
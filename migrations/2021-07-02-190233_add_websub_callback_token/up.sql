@@ -0,0 +1,2 @@
+ALTER TABLE websub_subscriptions
+ADD COLUMN callback_token TEXT;
@@ -11,12 +11,29 @@ pub fn setup(app_m: &ArgMatches) -> Result<config::Config, Box<dyn Error>> {
         // TODO: split this up so subcommands can contribute defaults?
         .set_default("http_server_address", "0.0.0.0:3010")?
         .set_default("http_server_static_path", "./www/")?
-        .set_default("fetch_feeds_filename", "feed-urls.txt")?
+        .set_default(
+            "http_user_agent",
+            concat!("feedspool-rs/", env!("CARGO_PKG_VERSION")),
+        )?
+        .set_default("http_proxy", "")?
+        .set_default("credentials_path", "credentials.toml")?
+        .set_default("websub_callback_url", "")?
+        .set_default("websub_lease_seconds", 60 * 60 * 24 * 7)?
+        .set_default("rsscloud_callback_url", "")?
         .set_default("fetch_retain_src", false)?
         .set_default("fetch_skip_entry_update", true)?
         .set_default("fetch_min_fetch_period", 60 * 30)?
+        .set_default("fetch_max_fetch_period", 60 * 60 * 24)?
         .set_default("fetch_request_timeout", 5)?
         .set_default("fetch_concurrency_limit", 16)?
+        .set_default("fetch_daemon_cycle_period", 60)?
+        .set_default("fetch_daemon_sources_period", 60 * 30)?
+        .set_default("fetch_max_consecutive_errors", 10)?
+        .set_default("fetch_retry_max_attempts", 3)?
+        .set_default("fetch_retry_base_delay", 2)?
+        .set_default("fetch_retry_jitter", 1)?
+        .set_default("fetch_host_concurrency_limit", 2)?
+        .set_default("fetch_host_request_delay", 1)?
         .merge(config::File::with_name("config").required(false))?
         .merge(config::Environment::with_prefix("APP"))?;
 
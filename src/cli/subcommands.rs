@@ -1,17 +1,34 @@
 use clap::App;
 use clap::ArgMatches;
 use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead};
+use std::path::Path;
 
+pub mod discover;
+pub mod export_opml;
+pub mod feeds;
 pub mod fetch;
+pub mod import_opml;
 pub mod render;
 pub mod serve;
+pub mod subscribe;
+pub mod subscriptions;
 pub mod toplinks;
+pub mod unsubscribe;
 
 pub fn setup(app: App<'static>) -> App<'static> {
     app.subcommand(fetch::app())
         .subcommand(serve::app())
         .subcommand(render::app())
         .subcommand(toplinks::app())
+        .subcommand(subscribe::app())
+        .subcommand(unsubscribe::app())
+        .subcommand(subscriptions::app())
+        .subcommand(import_opml::app())
+        .subcommand(export_opml::app())
+        .subcommand(discover::app())
+        .subcommand(feeds::app())
 }
 
 pub async fn execute(config: &config::Config, app_m: ArgMatches) -> Result<(), Box<dyn Error>> {
@@ -22,6 +39,23 @@ pub async fn execute(config: &config::Config, app_m: ArgMatches) -> Result<(), B
         Some((serve::NAME, sub_m)) => serve::execute(&sub_m, &config).await,
         Some((render::NAME, sub_m)) => render::execute(&sub_m, &config).await,
         Some((toplinks::NAME, sub_m)) => toplinks::execute(&sub_m, &config).await,
+        Some((subscribe::NAME, sub_m)) => subscribe::execute(&sub_m, &config).await,
+        Some((unsubscribe::NAME, sub_m)) => unsubscribe::execute(&sub_m, &config).await,
+        Some((subscriptions::NAME, sub_m)) => subscriptions::execute(&sub_m, &config).await,
+        Some((import_opml::NAME, sub_m)) => import_opml::execute(&sub_m, &config).await,
+        Some((export_opml::NAME, sub_m)) => export_opml::execute(&sub_m, &config).await,
+        Some((discover::NAME, sub_m)) => discover::execute(&sub_m, &config).await,
+        Some((feeds::NAME, sub_m)) => feeds::execute(&sub_m, &config).await,
         _ => Ok(()),
     }
 }
+
+// The output is wrapped in a Result to allow matching on errors
+// Returns an Iterator to the Reader of the lines of the file.
+pub fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
+where
+    P: AsRef<Path>,
+{
+    let file = File::open(filename)?;
+    Ok(io::BufReader::new(file).lines())
+}
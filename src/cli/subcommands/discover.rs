@@ -0,0 +1,35 @@
+use std::error::Error;
+use std::time::Duration;
+
+use clap::{App, Arg, ArgMatches};
+
+use feedspool::feeds::discovery;
+
+use super::fetch;
+
+pub const NAME: &str = "discover";
+
+pub fn app() -> App<'static> {
+    App::new(NAME)
+        .about("Discover feeds offered by a web page")
+        .arg(Arg::new("url").about("URL of web page").required(true))
+}
+
+pub async fn execute(matches: &ArgMatches, config: &config::Config) -> Result<(), Box<dyn Error>> {
+    let url = matches.value_of("url").unwrap();
+    let request_timeout = Duration::from_secs(config.get("fetch_request_timeout")?);
+    let client = fetch::http_client(config)?;
+    let feed_links = discovery::discover_feeds(&client, url, request_timeout).await?;
+    if feed_links.is_empty() {
+        log::warn!("No feeds found at {}", url);
+    }
+    for feed_link in feed_links {
+        println!(
+            "{}\t{}\t{}",
+            feed_link.url,
+            feed_link.media_type.unwrap_or_default(),
+            feed_link.title.unwrap_or_default()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,39 @@
+use std::error::Error;
+use std::fs::File;
+use std::io;
+
+use clap::{App, Arg, ArgMatches};
+
+use feedspool::{db, opml};
+
+pub const NAME: &str = "export-opml";
+
+pub fn app() -> App<'static> {
+    App::new(NAME)
+        .about("Export feeds as OPML")
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .about("Filename for OPML output, rather than stdout")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("title")
+                .long("title")
+                .about("Title for OPML document")
+                .takes_value(true)
+                .default_value(opml::DEFAULT_TITLE),
+        )
+}
+
+pub async fn execute(matches: &ArgMatches, config: &config::Config) -> Result<(), Box<dyn Error>> {
+    let conn = db::connect(&config)?;
+    let outlines = opml::find_outlines(&conn)?;
+    let title = matches.value_of("title").unwrap();
+    match matches.value_of("output") {
+        Some(filename) => opml::write(File::create(filename)?, title, &outlines)?,
+        None => opml::write(io::stdout(), title, &outlines)?,
+    };
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use std::error::Error;
+
+use clap::{App, AppSettings, Arg, ArgMatches};
+
+use feedspool::db;
+
+pub const NAME: &str = "feeds";
+
+const MERGE: &str = "merge";
+const PAUSED: &str = "paused";
+const RESUME: &str = "resume";
+
+pub fn app() -> App<'static> {
+    App::new(NAME)
+        .about("Manage fetched feeds")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            App::new(MERGE)
+                .about("Merge entries and history of a feed into a feed at another URL")
+                .arg(Arg::new("from").about("Old URL of feed").required(true))
+                .arg(Arg::new("to").about("New URL of feed").required(true)),
+        )
+        .subcommand(App::new(PAUSED).about("List feeds paused after repeated errors"))
+        .subcommand(
+            App::new(RESUME)
+                .about("Resume polling paused feeds")
+                .arg(
+                    Arg::new("urls")
+                        .about("URLs of feeds")
+                        .multiple(true)
+                        .required_unless_present("all"),
+                )
+                .arg(Arg::new("all").long("all").about("Resume all paused feeds")),
+        )
+}
+
+pub async fn execute(matches: &ArgMatches, config: &config::Config) -> Result<(), Box<dyn Error>> {
+    match matches.subcommand() {
+        Some((MERGE, sub_m)) => merge(&sub_m, &config),
+        Some((PAUSED, sub_m)) => list_paused(&sub_m, &config),
+        Some((RESUME, sub_m)) => resume(&sub_m, &config),
+        _ => Ok(()),
+    }
+}
+
+fn merge(matches: &ArgMatches, config: &config::Config) -> Result<(), Box<dyn Error>> {
+    let from_url = matches.value_of("from").unwrap();
+    let to_url = matches.value_of("to").unwrap();
+    let conn = db::connect(&config)?;
+    db::merge_feed(&conn, from_url, to_url)?;
+    log::info!("Merged {} into {}", from_url, to_url);
+    Ok(())
+}
+
+fn list_paused(_matches: &ArgMatches, config: &config::Config) -> Result<(), Box<dyn Error>> {
+    let conn = db::connect(&config)?;
+    for subscription in db::find_paused_subscriptions(&conn)? {
+        if let Some(url) = subscription.url {
+            println!(
+                "{}\t{}\t{}",
+                url,
+                subscription.paused_at.unwrap_or_default(),
+                subscription.paused_reason.unwrap_or_default()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn resume(matches: &ArgMatches, config: &config::Config) -> Result<(), Box<dyn Error>> {
+    let conn = db::connect(&config)?;
+    let urls: Vec<String> = if matches.is_present("all") {
+        db::find_paused_subscriptions(&conn)?
+            .into_iter()
+            .filter_map(|subscription| subscription.url)
+            .collect()
+    } else {
+        matches
+            .values_of("urls")
+            .unwrap()
+            .map(String::from)
+            .collect()
+    };
+    for url in urls {
+        if db::resume_subscription(&conn, &url)? {
+            log::info!("Resumed {}", url);
+        } else {
+            log::warn!("No paused subscription for {}", url);
+        }
+    }
+    Ok(())
+}
@@ -1,100 +1,540 @@
+use std::collections::HashMap;
 use std::error::Error;
-use std::fs::File;
-use std::io::{self, BufRead};
-use std::path::Path;
+use std::fmt;
+use std::future::Future;
 use std::str;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use clap::{App, Arg, ArgMatches};
 use futures::stream::{self, StreamExt};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use tokio::sync::Notify;
 
-use feedspool::feeds::result::{FeedPollError, FeedPollResult};
+use feedspool::db::writer::DbWriter;
+use feedspool::feeds::client::ClientOptions;
+use feedspool::feeds::credentials::CredentialStore;
+use feedspool::feeds::hosts::{HostLimiter, HostLimits};
+use feedspool::feeds::local;
+use feedspool::feeds::options::{PollOptions, PollOverrides};
+use feedspool::feeds::result::{FeedPollError, FeedPollPreview, FeedPollResult};
+use feedspool::feeds::retry::RetryPolicy;
+use feedspool::feeds::schedule::FetchSchedule;
+use feedspool::rsscloud::RssCloudOptions;
+use feedspool::websub::WebSubOptions;
 use feedspool::{db, feeds};
 
+use super::{read_lines, subscriptions};
+
+mod report;
+
+use report::{FailureThreshold, FeedReport, FetchSummary, PollOutcome, ReportFormat, Reporter};
+
 pub const NAME: &str = "fetch";
 
+/// A run where more feeds failed than `--max-failures` allows
+#[derive(Debug)]
+pub struct TooManyFailures {
+    failed: usize,
+    polled: usize,
+    max_failures: FailureThreshold,
+}
+
+impl TooManyFailures {
+    /// Exit code for the process when a run has too many failures
+    pub const EXIT_CODE: i32 = 2;
+}
+
+impl fmt::Display for TooManyFailures {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} feeds failed, more than the {} allowed",
+            self.failed, self.polled, self.max_failures
+        )
+    }
+}
+
+impl Error for TooManyFailures {}
+
 pub fn app() -> App<'static> {
-    App::new(NAME).about("Fetch a feed").arg(
-        Arg::new("feeds")
-            .long("feeds")
-            .about("Filename of feeds list")
-            .takes_value(true),
-    )
+    App::new(NAME)
+        .about("Fetch a feed")
+        .arg(
+            Arg::new("feeds")
+                .long("feeds")
+                .about("Filename of feeds list to fetch instead of subscriptions")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("url")
+                .long("url")
+                .about("URL of a single feed to fetch instead of subscriptions, even if not due")
+                .takes_value(true)
+                .conflicts_with("feeds"),
+        )
+        .arg(
+            Arg::new("daemon")
+                .long("daemon")
+                .about("Keep running, polling feeds as they come due, until terminated")
+                .conflicts_with("url"),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .long("dry-run")
+                .about("Fetch and parse feeds, printing what would change without changing anything")
+                .conflicts_with_all(&["daemon", "report", "max-failures"]),
+        )
+        .arg(
+            Arg::new("report")
+                .long("report")
+                .about("Write a report of each feed polled and a summary to stdout")
+                .takes_value(true)
+                .possible_values(&ReportFormat::NAMES),
+        )
+        .arg(
+            Arg::new("max-failures")
+                .long("max-failures")
+                .about(
+                    "Exit with status 2 if more feeds than this failed, as a count or a percentage like 10%",
+                )
+                .takes_value(true)
+                .conflicts_with("daemon"),
+        )
 }
 
 pub async fn execute(matches: &ArgMatches, config: &config::Config) -> Result<(), Box<dyn Error>> {
-    let concurrency_limit = config.get::<usize>("fetch_concurrency_limit")?;
-    let min_fetch_period = Duration::from_secs(config.get("fetch_min_fetch_period")?);
-    let request_timeout = Duration::from_secs(config.get("fetch_request_timeout")?);
-    let retain_src = config.get("fetch_retain_src")?;
-    let skip_entry_update = config.get("fetch_skip_entry_update")?;
-
-    let feeds_filename = match matches.value_of("feeds") {
-        Some(filename) => String::from(filename),
-        None => config.get("fetch_feeds_filename")?,
+    let max_failures = matches
+        .value_of("max-failures")
+        .map(FailureThreshold::parse)
+        .transpose()?;
+    let mut default_options = poll_options(config)?;
+    if matches.is_present("url") {
+        default_options.schedule = FetchSchedule::Now;
+    }
+    let db_writer = DbWriter::new(&db::create_pool(config)?)?;
+
+    if matches.is_present("daemon") {
+        return run_daemon(matches, config, &db_writer, &default_options).await;
+    }
+
+    if matches.is_present("dry-run") {
+        return preview_all(matches, config, &db_writer, &default_options).await;
+    }
+
+    if matches.value_of("feeds").is_none() && matches.value_of("url").is_none() {
+        subscriptions::sync_sources(&config, &db_writer, &default_options.client).await?;
+    }
+    let summary = fetch_all(
+        matches,
+        config,
+        &db_writer,
+        &default_options,
+        &AtomicBool::new(false),
+    )
+    .await?;
+    log::info!("ALL DONE! {}", summary);
+    if let Some(max_failures) = max_failures {
+        if max_failures.is_exceeded_by(&summary) {
+            return Err(Box::new(TooManyFailures {
+                failed: summary.failed,
+                polled: summary.polled(),
+                max_failures,
+            }));
+        }
+    }
+    Ok(())
+}
+
+/// Build HTTP client settings from config
+///
+/// # Errors
+///
+/// Will return Err for any missing or invalid config setting
+pub fn client_options(config: &config::Config) -> Result<ClientOptions, Box<dyn Error>> {
+    let non_empty = |value: String| Some(value).filter(|value| !value.is_empty());
+    let mut headers = HeaderMap::new();
+    let configured_headers = match config.get::<HashMap<String, String>>("http_headers") {
+        Err(config::ConfigError::NotFound(_)) => HashMap::new(),
+        configured_headers => configured_headers?,
     };
+    for (name, value) in configured_headers {
+        headers.insert(
+            HeaderName::from_bytes(name.as_bytes())?,
+            HeaderValue::from_str(&value)?,
+        );
+    }
+    Ok(ClientOptions {
+        user_agent: non_empty(config.get("http_user_agent")?),
+        proxy: non_empty(config.get("http_proxy")?),
+        headers,
+    })
+}
 
-    let feeds = read_lines(feeds_filename)?;
-    let fut = stream::iter(feeds).for_each_concurrent(concurrency_limit, |url_try| async move {
-        if let Ok(url) = url_try {
-            log::info!("Fetching {}", &url);
-            let conn_try = db::connect(&config);
-            if let Err(err) = conn_try {
-                log::error!("Error connection to DB - {}", err);
-            } else if let Ok(conn) = conn_try {
-                match feeds::poll_one_feed(
-                    &conn,
-                    &url,
-                    request_timeout,
-                    min_fetch_period,
-                    retain_src,
-                    skip_entry_update,
-                )
-                .await
-                {
-                    Ok(fetch_result) => match fetch_result {
-                        FeedPollResult::Skipped => {
-                            log::info!("Skipped update for {}", url)
-                        }
-                        FeedPollResult::NotModified { .. } => {
-                            log::info!("No updates for {}", url)
-                        }
-                        FeedPollResult::Updated { .. } => log::info!("Updated {}", url),
-                        _ => log::info!("Unexpected result {} {:?}", url, fetch_result),
-                    },
-                    Err(error) => match error {
-                        FeedPollError::FetchFailed { fetch } => {
-                            log::error!("Fetch failed with status {} for {}", fetch.status, url)
-                        }
-                        FeedPollError::NotFound(_) => {
-                            log::error!("Not found error for {}", url)
-                        }
-                        FeedPollError::Timedout(_) => {
-                            log::error!("Fetch timed out for {}", url)
-                        }
-                        FeedPollError::ParseError { error, .. } => {
-                            log::error!("Feed parsing failed for {} - {:?}", url, error)
-                        }
-                        FeedPollError::UpdateError { error, .. } => {
-                            log::error!("Databse update failed for {} - {:?}", url, error)
-                        }
-                        _ => log::error!("Error polling feed {} - {:?}", url, error),
-                    },
-                }
+/// Build an HTTP client from config, to be shared by all requests in a run
+///
+/// # Errors
+///
+/// Will return Err for any missing or invalid config setting
+pub fn http_client(config: &config::Config) -> Result<reqwest::Client, Box<dyn Error>> {
+    Ok(client_options(config)?.build()?)
+}
+
+/// Build the default options for polling feeds from config
+///
+/// # Errors
+///
+/// Will return Err for any missing or invalid config setting
+pub fn poll_options(config: &config::Config) -> Result<PollOptions, Box<dyn Error>> {
+    let default_host_limits = HostLimits {
+        concurrency_limit: config.get("fetch_host_concurrency_limit")?,
+        request_delay: config.get("fetch_host_request_delay")?,
+    };
+    let host_limits = match config.get::<HashMap<String, HostLimits>>("fetch_hosts") {
+        Err(config::ConfigError::NotFound(_)) => HashMap::new(),
+        host_limits => host_limits?,
+    };
+    let websub_callback_url = config.get::<String>("websub_callback_url")?;
+    let websub = if websub_callback_url.is_empty() {
+        None
+    } else {
+        Some(WebSubOptions {
+            callback_base_url: websub_callback_url,
+            lease_seconds: config.get("websub_lease_seconds")?,
+        })
+    };
+    let rsscloud_callback_url = config.get::<String>("rsscloud_callback_url")?;
+    let rsscloud = if rsscloud_callback_url.is_empty() {
+        None
+    } else {
+        Some(RssCloudOptions {
+            callback_base_url: rsscloud_callback_url,
+        })
+    };
+    Ok(PollOptions {
+        request_timeout: Duration::from_secs(config.get("fetch_request_timeout")?),
+        min_fetch_period: Duration::from_secs(config.get("fetch_min_fetch_period")?),
+        max_fetch_period: Duration::from_secs(config.get("fetch_max_fetch_period")?),
+        retain_src: config.get("fetch_retain_src")?,
+        skip_entry_update: config.get("fetch_skip_entry_update")?,
+        max_consecutive_errors: config.get("fetch_max_consecutive_errors")?,
+        retry: RetryPolicy {
+            max_attempts: config.get("fetch_retry_max_attempts")?,
+            base_delay: Duration::from_secs(config.get("fetch_retry_base_delay")?),
+            jitter: Duration::from_secs(config.get("fetch_retry_jitter")?),
+        },
+        client: http_client(config)?,
+        headers: HeaderMap::new(),
+        credentials: None,
+        credential_store: Arc::new(CredentialStore::load(
+            &config.get::<String>("credentials_path")?,
+        )?),
+        host_limiter: Arc::new(HostLimiter::new(default_host_limits, host_limits)),
+        websub,
+        rsscloud,
+        schedule: FetchSchedule::WhenDue,
+    })
+}
+
+/// Wait for SIGTERM or Ctrl-C
+#[cfg(unix)]
+fn shutdown_signal() -> Result<impl Future<Output = ()>, std::io::Error> {
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+    Ok(async move {
+        tokio::select! {
+            _ = terminate.recv() => {},
+            _ = tokio::signal::ctrl_c() => {},
+        }
+    })
+}
+
+/// Wait for Ctrl-C, the only shutdown signal outside of unix
+#[cfg(not(unix))]
+fn shutdown_signal() -> Result<impl Future<Output = ()>, std::io::Error> {
+    Ok(async {
+        let _ = tokio::signal::ctrl_c().await;
+    })
+}
+
+/// Poll feeds in cycles until SIGTERM or Ctrl-C. Each cycle reloads the feeds
+/// to poll, leaving `poll_one_feed` to skip those that aren't due yet, while
+/// subscription sources are synced every `fetch_daemon_sources_period`.
+/// On shutdown, feeds already being polled are allowed to finish.
+async fn run_daemon(
+    matches: &ArgMatches,
+    config: &config::Config,
+    db_writer: &DbWriter,
+    default_options: &PollOptions,
+) -> Result<(), Box<dyn Error>> {
+    let cycle_period = Duration::from_secs(config.get("fetch_daemon_cycle_period")?);
+    let sources_sync_period = Duration::from_secs(config.get("fetch_daemon_sources_period")?);
+
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let shutdown_notify = Arc::new(Notify::new());
+    {
+        let shutdown = shutdown.clone();
+        let shutdown_notify = shutdown_notify.clone();
+        let shutdown_signal = shutdown_signal()?;
+        tokio::spawn(async move {
+            shutdown_signal.await;
+            log::info!("Shutting down once feeds in progress are done");
+            shutdown.store(true, Ordering::SeqCst);
+            shutdown_notify.notify();
+        });
+    }
+
+    let mut cycle = 0;
+    let mut last_sources_sync: Option<Instant> = None;
+    while !shutdown.load(Ordering::SeqCst) {
+        cycle += 1;
+        let cycle_start = Instant::now();
+        if matches.value_of("feeds").is_none()
+            && last_sources_sync.map_or(true, |last| last.elapsed() >= sources_sync_period)
+        {
+            if let Err(error) =
+                subscriptions::sync_sources(&config, db_writer, &default_options.client).await
+            {
+                log::error!("Error syncing subscription sources - {}", error);
             }
+            last_sources_sync = Some(Instant::now());
+        }
+        match fetch_all(matches, config, db_writer, default_options, &shutdown).await {
+            Ok(summary) => log::info!(
+                "Cycle {} done in {:.1}s - {}",
+                cycle,
+                cycle_start.elapsed().as_secs_f64(),
+                summary
+            ),
+            Err(error) => log::error!("Cycle {} failed - {}", cycle, error),
+        }
+        let wait = cycle_period
+            .checked_sub(cycle_start.elapsed())
+            .unwrap_or_default();
+        tokio::select! {
+            _ = tokio::time::delay_for(wait) => {},
+            _ = shutdown_notify.notified() => {},
         }
-    });
-    fut.await;
+    }
     log::info!("ALL DONE!");
     Ok(())
 }
 
-// The output is wrapped in a Result to allow matching on errors
-// Returns an Iterator to the Reader of the lines of the file.
-fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
-where
-    P: AsRef<Path>,
-{
-    let file = File::open(filename)?;
-    Ok(io::BufReader::new(file).lines())
+/// Load the feeds to poll from `--url`, `--feeds` or the active subscriptions.
+/// Standard input can only be read once, so daemon mode refuses to poll it.
+async fn load_feeds(
+    matches: &ArgMatches,
+    db_writer: &DbWriter,
+) -> Result<Vec<(String, PollOverrides)>, Box<dyn Error>> {
+    if let Some(url) = matches.value_of("url") {
+        return Ok(vec![(String::from(url), PollOverrides::default())]);
+    }
+    let mut feeds: Vec<(String, PollOverrides)> = Vec::new();
+    match matches.value_of("feeds") {
+        Some(filename) => {
+            for line in read_lines(filename)? {
+                if let Some(feed) = PollOverrides::parse_feed_line(&line?)? {
+                    feeds.push(feed);
+                }
+            }
+        }
+        None => {
+            for subscription in db_writer.run(db::find_active_subscriptions).await? {
+                let overrides = PollOverrides::from(&subscription);
+                let is_allowed = local::is_allowed_subscription(&subscription);
+                if let Some(url) = subscription.url {
+                    if is_allowed {
+                        feeds.push((url, overrides));
+                    } else {
+                        log::warn!(
+                            "Refusing to poll {} - local feeds can't come from a subscription source",
+                            url
+                        );
+                    }
+                }
+            }
+        }
+    };
+    if matches.is_present("daemon") {
+        feeds.retain(|(url, _)| {
+            let is_stdin = url == local::STDIN_URL;
+            if is_stdin {
+                log::warn!(
+                    "Refusing to poll {} - standard input can't be read in daemon mode",
+                    url
+                );
+            }
+            !is_stdin
+        });
+    }
+    Ok(feeds)
+}
+
+/// Poll all feeds concurrently, skipping any not yet started once shutdown is
+/// requested, and report on each as it's done
+async fn fetch_all(
+    matches: &ArgMatches,
+    config: &config::Config,
+    db_writer: &DbWriter,
+    default_options: &PollOptions,
+    shutdown: &AtomicBool,
+) -> Result<FetchSummary, Box<dyn Error>> {
+    let concurrency_limit = config.get::<usize>("fetch_concurrency_limit")?;
+    let report_format = matches.value_of("report").and_then(ReportFormat::from_name);
+    let feeds = load_feeds(matches, db_writer).await?;
+    let mut reports = stream::iter(feeds)
+        .map(|(url, overrides)| async move {
+            if shutdown.load(Ordering::SeqCst) {
+                return FeedReport::skipped(&url);
+            }
+            log::info!("Fetching {}", &url);
+            let options = default_options.with_overrides(&overrides);
+            let started = Instant::now();
+            let result = feeds::poll_one_feed(db_writer, &url, &options).await;
+            let outcome = log_poll_result(&url, &result);
+            FeedReport::new(&url, outcome, &result, started.elapsed())
+        })
+        .buffer_unordered(concurrency_limit);
+    let mut reporter = Reporter::new(report_format);
+    while let Some(report) = reports.next().await {
+        reporter.add(report)?;
+    }
+    reporter.finish()
+}
+
+/// Fetch all feeds concurrently without changing anything, printing what
+/// polling each would change as it's done
+async fn preview_all(
+    matches: &ArgMatches,
+    config: &config::Config,
+    db_writer: &DbWriter,
+    default_options: &PollOptions,
+) -> Result<(), Box<dyn Error>> {
+    let concurrency_limit = config.get::<usize>("fetch_concurrency_limit")?;
+    let feeds = load_feeds(matches, db_writer).await?;
+    let mut previews = stream::iter(feeds)
+        .map(|(url, overrides)| async move {
+            log::info!("Fetching {}", &url);
+            let options = default_options.with_overrides(&overrides);
+            let preview = feeds::preview_one_feed(db_writer, &url, &options).await;
+            (url, preview)
+        })
+        .buffer_unordered(concurrency_limit);
+    while let Some((url, preview)) = previews.next().await {
+        match preview {
+            Ok(preview) => print_preview(&url, &preview),
+            Err(error) => {
+                println!("{} - failed ({})", url, error.category());
+                log_poll_result(&url, &Err(error));
+            }
+        }
+    }
+    log::info!("ALL DONE! Nothing was changed");
+    Ok(())
+}
+
+fn print_preview(url: &str, preview: &FeedPollPreview) {
+    let fetch = match &preview.result {
+        FeedPollResult::Fetched { fetch, .. } => fetch,
+        FeedPollResult::NotModified { fetch } => {
+            println!("{} - not modified ({})", url, fetch.status);
+            return;
+        }
+        FeedPollResult::Unchanged { fetch, .. } => {
+            println!("{} - content unchanged ({})", url, fetch.status);
+            return;
+        }
+        _ => return,
+    };
+    println!(
+        "{} - {} new, {} updated, {} defunct entries ({})",
+        url,
+        preview.new_entries.len(),
+        preview.updated_entries.len(),
+        preview.defunct_entries.len(),
+        fetch.status
+    );
+    if let Some(moved_to) = &fetch.moved_to {
+        println!("  moved permanently to {}", moved_to);
+    }
+    for (marker, entries) in &[
+        ("new", &preview.new_entries),
+        ("updated", &preview.updated_entries),
+        ("defunct", &preview.defunct_entries),
+    ] {
+        for entry in entries.iter() {
+            println!(
+                "  {:<8}{} {}",
+                marker,
+                entry.guid,
+                entry.title.as_deref().unwrap_or("")
+            );
+        }
+    }
+}
+
+fn log_poll_result(url: &str, result: &Result<FeedPollResult, FeedPollError>) -> PollOutcome {
+    match result {
+        Ok(fetch_result) => match fetch_result {
+            FeedPollResult::Skipped => {
+                log::info!("Skipped update for {}", url);
+                PollOutcome::Skipped
+            }
+            FeedPollResult::NotModified { .. } => {
+                log::info!("No updates for {}", url);
+                PollOutcome::NotModified
+            }
+            FeedPollResult::Unchanged { .. } => {
+                log::info!("Content unchanged for {}", url);
+                PollOutcome::Unchanged
+            }
+            FeedPollResult::Updated { .. } => {
+                log::info!("Updated {}", url);
+                PollOutcome::Updated
+            }
+            _ => {
+                log::info!("Unexpected result {} {:?}", url, fetch_result);
+                PollOutcome::Skipped
+            }
+        },
+        Err(error) => {
+            match error {
+                FeedPollError::FetchFailed { fetch } => {
+                    log::error!("Fetch failed with status {} for {}", fetch.status, url)
+                }
+                FeedPollError::Gone { .. } => {
+                    log::warn!("Feed gone for {}, deactivated", url)
+                }
+                FeedPollError::NotFound(_) => {
+                    log::error!("Not found error for {}", url)
+                }
+                FeedPollError::Timedout(_) => {
+                    log::error!("Fetch timed out for {}", url)
+                }
+                FeedPollError::ReadError(error) => {
+                    log::error!("Reading failed for {} - {}", url, error)
+                }
+                FeedPollError::CommandFailed { status, stderr } => {
+                    log::error!("Command {} for {} - {}", status, url, stderr.trim())
+                }
+                FeedPollError::ParseError { error, .. } => {
+                    log::error!("Feed parsing failed for {} - {:?}", url, error)
+                }
+                FeedPollError::FeedsDiscovered { feed_links, .. } => log::error!(
+                    "Found a web page rather than a feed at {} - try subscribing to {}",
+                    url,
+                    feed_links
+                        .iter()
+                        .map(|feed_link| feed_link.url.as_str())
+                        .collect::<Vec<&str>>()
+                        .join(" or ")
+                ),
+                FeedPollError::UpdateError { error, .. } => {
+                    log::error!("Databse update failed for {} - {:?}", url, error)
+                }
+                _ => log::error!("Error polling feed {} - {:?}", url, error),
+            };
+            PollOutcome::Failed
+        }
+    }
 }
@@ -0,0 +1,259 @@
+use serde::Serialize;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use feedspool::feeds::result::{EntryCounts, FeedFetchResult, FeedPollError, FeedPollResult};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReportFormat {
+    /// One JSON document with all the feeds and a summary, written at the end
+    Json,
+    /// One JSON object per line for each feed as it's done, then the summary
+    Ndjson,
+}
+
+impl ReportFormat {
+    pub const NAMES: [&'static str; 2] = ["json", "ndjson"];
+
+    pub fn from_name(name: &str) -> Option<ReportFormat> {
+        match name {
+            "json" => Some(ReportFormat::Json),
+            "ndjson" => Some(ReportFormat::Ndjson),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PollOutcome {
+    Updated,
+    NotModified,
+    Unchanged,
+    Skipped,
+    Failed,
+}
+
+/// What happened when polling one feed
+#[derive(Debug, Serialize)]
+pub struct FeedReport {
+    pub url: String,
+    pub outcome: PollOutcome,
+    pub status: Option<u16>,
+    pub new_entries: usize,
+    pub updated_entries: usize,
+    pub duration_ms: u64,
+    pub error: Option<&'static str>,
+}
+
+impl FeedReport {
+    pub fn new(
+        url: &str,
+        outcome: PollOutcome,
+        result: &Result<FeedPollResult, FeedPollError>,
+        duration: Duration,
+    ) -> FeedReport {
+        let fetch = match result {
+            Ok(result) => result.fetch(),
+            Err(error) => error.fetch(),
+        };
+        let entries = match result {
+            Ok(FeedPollResult::Updated { entries, .. }) => *entries,
+            _ => EntryCounts::default(),
+        };
+        FeedReport {
+            url: String::from(url),
+            outcome,
+            status: fetch
+                .and_then(FeedFetchResult::status_code)
+                .map(|status| status.as_u16()),
+            new_entries: entries.new,
+            updated_entries: entries.updated,
+            duration_ms: duration_millis(duration),
+            error: result.as_ref().err().map(FeedPollError::category),
+        }
+    }
+
+    /// Report for a feed that wasn't polled at all
+    pub fn skipped(url: &str) -> FeedReport {
+        FeedReport {
+            url: String::from(url),
+            outcome: PollOutcome::Skipped,
+            status: None,
+            new_entries: 0,
+            updated_entries: 0,
+            duration_ms: 0,
+            error: None,
+        }
+    }
+}
+
+/// Counts of feed poll outcomes for a fetch run
+#[derive(Debug, Default, Serialize)]
+pub struct FetchSummary {
+    pub feeds: usize,
+    pub updated: usize,
+    pub not_modified: usize,
+    pub unchanged: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub new_entries: usize,
+    pub updated_entries: usize,
+    pub duration_ms: u64,
+}
+
+impl FetchSummary {
+    fn add(&mut self, feed: &FeedReport) {
+        self.feeds += 1;
+        match feed.outcome {
+            PollOutcome::Updated => self.updated += 1,
+            PollOutcome::NotModified => self.not_modified += 1,
+            PollOutcome::Unchanged => self.unchanged += 1,
+            PollOutcome::Skipped => self.skipped += 1,
+            PollOutcome::Failed => self.failed += 1,
+        }
+        self.new_entries += feed.new_entries;
+        self.updated_entries += feed.updated_entries;
+    }
+
+    /// Number of feeds actually polled, rather than skipped as not yet due
+    pub fn polled(&self) -> usize {
+        self.feeds - self.skipped
+    }
+}
+
+impl fmt::Display for FetchSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} updated, {} not modified, {} unchanged, {} skipped, {} failed",
+            self.updated, self.not_modified, self.unchanged, self.skipped, self.failed
+        )
+    }
+}
+
+/// A line of an ndjson report
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ReportLine<'a> {
+    Feed(&'a FeedReport),
+    Summary(&'a FetchSummary),
+}
+
+#[derive(Serialize)]
+struct ReportDocument<'a> {
+    feeds: &'a [FeedReport],
+    summary: &'a FetchSummary,
+}
+
+/// Collects feed reports for a fetch run into a summary, writing them to
+/// stdout in the requested format, if any
+#[derive(Debug)]
+pub struct Reporter {
+    format: Option<ReportFormat>,
+    feeds: Vec<FeedReport>,
+    summary: FetchSummary,
+    started: Instant,
+}
+
+impl Reporter {
+    pub fn new(format: Option<ReportFormat>) -> Reporter {
+        Reporter {
+            format,
+            feeds: Vec::new(),
+            summary: FetchSummary::default(),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn add(&mut self, feed: FeedReport) -> Result<(), Box<dyn Error>> {
+        self.summary.add(&feed);
+        match self.format {
+            Some(ReportFormat::Ndjson) => {
+                println!("{}", serde_json::to_string(&ReportLine::Feed(&feed))?);
+            }
+            Some(ReportFormat::Json) => self.feeds.push(feed),
+            None => {}
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<FetchSummary, Box<dyn Error>> {
+        self.summary.duration_ms = duration_millis(self.started.elapsed());
+        match self.format {
+            Some(ReportFormat::Ndjson) => {
+                println!(
+                    "{}",
+                    serde_json::to_string(&ReportLine::Summary(&self.summary))?
+                );
+            }
+            Some(ReportFormat::Json) => {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&ReportDocument {
+                        feeds: &self.feeds,
+                        summary: &self.summary,
+                    })?
+                );
+            }
+            None => {}
+        }
+        Ok(self.summary)
+    }
+}
+
+/// Most failed feeds allowed in a run before it's considered a failure,
+/// either a count like `5` or a percentage of feeds polled like `10%`, not
+/// counting those skipped as not yet due
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FailureThreshold {
+    Count(usize),
+    Percent(f64),
+}
+
+impl FailureThreshold {
+    pub fn parse(value: &str) -> Result<FailureThreshold, String> {
+        let invalid = || format!("invalid failure threshold {:?}", value);
+        match value.trim().strip_suffix('%') {
+            Some(percent) => percent
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|percent| (0.0..=100.0).contains(percent))
+                .map(FailureThreshold::Percent)
+                .ok_or_else(invalid),
+            None => value
+                .trim()
+                .parse::<usize>()
+                .map(FailureThreshold::Count)
+                .map_err(|_| invalid()),
+        }
+    }
+
+    /// Whether a run had more failed feeds than allowed
+    pub fn is_exceeded_by(&self, summary: &FetchSummary) -> bool {
+        match self {
+            FailureThreshold::Count(count) => summary.failed > *count,
+            #[allow(clippy::cast_precision_loss)]
+            FailureThreshold::Percent(percent) => {
+                summary.failed > 0
+                    && summary.failed as f64 * 100.0 > summary.polled() as f64 * percent
+            }
+        }
+    }
+}
+
+impl fmt::Display for FailureThreshold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailureThreshold::Count(count) => write!(f, "{}", count),
+            FailureThreshold::Percent(percent) => write!(f, "{}%", percent),
+        }
+    }
+}
+
+fn duration_millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
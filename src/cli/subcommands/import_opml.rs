@@ -0,0 +1,62 @@
+use chrono::prelude::*;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+
+use clap::{App, Arg, ArgMatches};
+
+use feedspool::feeds::local;
+use feedspool::{db, models, opml};
+
+pub const NAME: &str = "import-opml";
+
+pub fn app() -> App<'static> {
+    App::new(NAME).about("Subscribe to feeds from OPML").arg(
+        Arg::new("file")
+            .about("Filename of OPML document")
+            .required(true),
+    )
+}
+
+pub async fn execute(matches: &ArgMatches, config: &config::Config) -> Result<(), Box<dyn Error>> {
+    let filename = matches.value_of("file").unwrap();
+    let outlines = opml::parse(BufReader::new(File::open(filename)?))?;
+
+    let now = Utc::now().to_rfc3339();
+    let conn = db::connect(&config)?;
+    let mut count = 0;
+    let mut new_count = 0;
+    for outline in &outlines {
+        // Whoever wrote the OPML could otherwise run commands or read files here
+        if local::is_local_source(&outline.xml_url) {
+            log::warn!(
+                "Skipped {} - local files and commands can't be imported from OPML",
+                outline.xml_url
+            );
+            continue;
+        }
+        count += 1;
+        let upsert = models::SubscriptionUpsert {
+            url: &outline.xml_url,
+            title: outline.title.as_deref(),
+            category: outline.category.as_deref(),
+            source_id: None,
+            active: Some(true),
+            now: &now,
+        };
+        if db::upsert_subscription(&conn, &upsert)? {
+            log::info!("Subscribed to {}", outline.xml_url);
+            new_count += 1;
+        } else {
+            log::debug!("Already subscribed to {}", outline.xml_url);
+        }
+    }
+    log::info!(
+        "Imported {} feeds from {} ({} new)",
+        count,
+        filename,
+        new_count
+    );
+
+    Ok(())
+}
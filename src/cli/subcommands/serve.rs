@@ -1,25 +1,41 @@
 use clap::{App, ArgMatches};
-use feedspool::db;
+use feedspool::db::writer::DbWriter;
+use feedspool::feeds::local;
+use feedspool::feeds::options::{PollOptions, PollOverrides};
+use feedspool::feeds::poll_one_feed;
+use feedspool::feeds::result::FeedPollResult;
+use feedspool::feeds::schedule::FetchSchedule;
 use feedspool::gql::{mutation::RootMutation, query::RootQuery, Context};
+use feedspool::{db, opml, rsscloud, websub};
 use hyper::{
+    header::{HeaderValue, CONTENT_TYPE},
     service::{make_service_fn, service_fn},
-    Body, Method, Response, Server, StatusCode,
+    Body, Method, Request, Response, Server, StatusCode,
 };
 use hyper_staticfile::Static;
 use juniper::{EmptySubscription, RootNode};
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::net::SocketAddr;
 use std::path::Path;
+use std::sync::{Mutex, PoisonError};
 use std::{convert::Infallible, sync::Arc};
+use tokio::sync::mpsc;
+
+use super::fetch;
 
 pub const NAME: &str = "serve";
 
+/// Most feeds announced as updated that can wait to be polled at once
+const NOTIFIED_QUEUE_LIMIT: usize = 256;
+
 pub fn app() -> App<'static> {
     App::new(NAME).about("Start web API server")
 }
 
 pub async fn execute(_matches: &ArgMatches, config: &config::Config) -> Result<(), Box<dyn Error>> {
     let db_pool = db::create_pool(config)?;
+    let db_writer = DbWriter::new(&db_pool)?;
 
     let root_node = Arc::new(RootNode::new(
         RootQuery,
@@ -31,18 +47,37 @@ pub async fn execute(_matches: &ArgMatches, config: &config::Config) -> Result<(
 
     let staticfiles = Static::new(Path::new(&config.get::<String>("http_server_static_path")?));
 
+    // Feeds announced as updated by rssCloud servers get polled in the background,
+    // while content pushed by WebSub hubs is stored with the same per-feed options
+    let poll_options = fetch::poll_options(config)?;
+    let (notified_sender, notified_receiver) = NotifiedQueue::new(NOTIFIED_QUEUE_LIMIT);
+    let notified_poller = poll_notified_feeds(
+        &db_writer,
+        &poll_options,
+        notified_sender.clone(),
+        notified_receiver,
+    );
+
     // TODO: move all this http server stuff into its own module outside of subcommands?
+    let service_db_writer = db_writer.clone();
+    let service_poll_options = Arc::new(poll_options.clone());
     let new_service = make_service_fn(move |_| {
         let root_node = root_node.clone();
         let ctx = ctx.clone();
+        let db_writer = service_db_writer.clone();
+        let poll_options = service_poll_options.clone();
         let staticfiles = staticfiles.clone();
+        let notified_sender = notified_sender.clone();
 
         // TODO: break down this indentation pyramid into separate functions?
-        async {
+        async move {
             Ok::<_, hyper::Error>(service_fn(move |req| {
                 let root_node = root_node.clone();
                 let ctx = ctx.clone();
+                let db_writer = db_writer.clone();
+                let poll_options = poll_options.clone();
                 let staticfiles = staticfiles.clone();
+                let notified_sender = notified_sender.clone();
                 async move {
                     Ok::<_, Infallible>(match (req.method(), req.uri().path()) {
                         (&Method::GET, "/graphiql") => {
@@ -51,6 +86,19 @@ pub async fn execute(_matches: &ArgMatches, config: &config::Config) -> Result<(
                         (&Method::GET, "/graphql") | (&Method::POST, "/graphql") => {
                             juniper_hyper::graphql(root_node, ctx, req).await
                         }
+                        (&Method::GET, "/opml") => opml_response(&ctx),
+                        (&Method::GET, path) if path.starts_with(websub::CALLBACK_PATH) => {
+                            websub_verify_response(&db_writer, &req).await
+                        }
+                        (&Method::POST, path) if path.starts_with(websub::CALLBACK_PATH) => {
+                            websub_content_response(&db_writer, req, poll_options).await
+                        }
+                        (&Method::GET, rsscloud::NOTIFY_PATH) => {
+                            rsscloud_verify_response(&db_writer, &req).await
+                        }
+                        (&Method::POST, rsscloud::NOTIFY_PATH) => {
+                            rsscloud_notify_response(&db_writer, req, &notified_sender).await
+                        }
                         _ => match staticfiles.serve(req).await {
                             Ok(resp) => resp,
                             Err(err) => {
@@ -69,9 +117,245 @@ pub async fn execute(_matches: &ArgMatches, config: &config::Config) -> Result<(
     let server = Server::bind(&server_addr).serve(new_service);
     println!("Listening on http://{}", server_addr);
 
-    if let Err(e) = server.await {
+    let (server_result, ()) = tokio::join!(server, notified_poller);
+    if let Err(e) = server_result {
         eprintln!("server error: {}", e)
     }
 
     Ok(())
 }
+
+fn opml_response(ctx: &Context) -> Response<Body> {
+    match export_opml(ctx) {
+        Ok(body) => {
+            let mut response = Response::new(Body::from(body));
+            response.headers_mut().insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("text/x-opml; charset=utf-8"),
+            );
+            response
+        }
+        Err(err) => {
+            let mut response = Response::new(Body::from(format!("{:?}", err)));
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            response
+        }
+    }
+}
+
+fn export_opml(ctx: &Context) -> Result<String, Box<dyn Error>> {
+    let conn = ctx.pool.get()?;
+    opml::export(&conn, opml::DEFAULT_TITLE)
+}
+
+fn websub_callback_token<T>(req: &Request<T>) -> String {
+    String::from(req.uri().path().trim_start_matches(websub::CALLBACK_PATH))
+}
+
+async fn websub_verify_response(db_writer: &DbWriter, req: &Request<Body>) -> Response<Body> {
+    let params: HashMap<String, String> =
+        url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+            .into_owned()
+            .collect();
+    let callback_token = websub_callback_token(req);
+    let verify_result = db_writer
+        .run(move |conn| websub::verify_intent(conn, &callback_token, &params))
+        .await
+        .map_err(|err| format!("{:?}", err));
+    match verify_result {
+        Ok(Some(challenge)) => Response::new(Body::from(challenge)),
+        Ok(None) => {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            response
+        }
+        Err(err) => {
+            let mut response = Response::new(Body::from(err));
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            response
+        }
+    }
+}
+
+async fn websub_content_response(
+    db_writer: &DbWriter,
+    req: Request<Body>,
+    poll_options: Arc<PollOptions>,
+) -> Response<Body> {
+    let callback_token = websub_callback_token(&req);
+    let headers = req.headers().clone();
+    let ingest_result = match hyper::body::to_bytes(req.into_body()).await {
+        Err(err) => Err(format!("{:?}", err)),
+        Ok(body) => {
+            let ingest_result = db_writer
+                .run(move |conn| {
+                    websub::ingest_content(conn, &callback_token, &headers, &body, &poll_options)
+                })
+                .await;
+            match ingest_result {
+                // Tell the hub to stop sending content for subscriptions we don't know
+                Err(websub::WebSubError::UnknownSubscription) => Ok(StatusCode::GONE),
+                Err(err) => Err(format!("{:?}", err)),
+                Ok(_) => Ok(StatusCode::NO_CONTENT),
+            }
+        }
+    };
+    match ingest_result {
+        Ok(status) => {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = status;
+            response
+        }
+        Err(err) => {
+            log::error!("Error ingesting WebSub content - {}", err);
+            let mut response = Response::new(Body::from(err));
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            response
+        }
+    }
+}
+
+async fn rsscloud_verify_response(db_writer: &DbWriter, req: &Request<Body>) -> Response<Body> {
+    let params: HashMap<String, String> =
+        url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+            .into_owned()
+            .collect();
+    let verify_result = db_writer
+        .run(move |conn| rsscloud::verify_challenge(conn, &params))
+        .await
+        .map_err(|err| format!("{:?}", err));
+    match verify_result {
+        Ok(Some(challenge)) => Response::new(Body::from(challenge)),
+        Ok(None) => {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            response
+        }
+        Err(err) => {
+            let mut response = Response::new(Body::from(err));
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            response
+        }
+    }
+}
+
+async fn rsscloud_notify_response(
+    db_writer: &DbWriter,
+    req: Request<Body>,
+    notified_sender: &NotifiedQueue,
+) -> Response<Body> {
+    let notify_result = match hyper::body::to_bytes(req.into_body()).await {
+        Err(err) => Err(format!("{:?}", err)),
+        Ok(body) => {
+            let feed_url = url::form_urlencoded::parse(&body)
+                .find(|(name, _)| name == "url")
+                .map(|(_, value)| value.into_owned())
+                .unwrap_or_default();
+            let registered_url = feed_url.clone();
+            db_writer
+                .run(move |conn| rsscloud::is_registered(conn, &registered_url))
+                .await
+                .map_err(|err| format!("{:?}", err))
+                .and_then(|is_registered| {
+                    if !is_registered {
+                        return Ok(StatusCode::NOT_FOUND);
+                    }
+                    if notified_sender.push(&feed_url) {
+                        log::info!("rssCloud notified update for {}", &feed_url);
+                        Ok(StatusCode::OK)
+                    } else {
+                        log::warn!("Too many rssCloud notifications, ignored {}", &feed_url);
+                        Ok(StatusCode::SERVICE_UNAVAILABLE)
+                    }
+                })
+        }
+    };
+    match notify_result {
+        Ok(status) => {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = status;
+            response
+        }
+        Err(err) => {
+            log::error!("Error handling rssCloud notification - {}", err);
+            let mut response = Response::new(Body::from(err));
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            response
+        }
+    }
+}
+
+/// Feeds announced as updated and waiting to be polled, each queued only
+/// once however many times it's announced, up to a limit
+#[derive(Clone)]
+struct NotifiedQueue {
+    inner: Arc<Mutex<(mpsc::Sender<String>, HashSet<String>)>>,
+}
+
+impl NotifiedQueue {
+    fn new(limit: usize) -> (NotifiedQueue, mpsc::Receiver<String>) {
+        let (sender, receiver) = mpsc::channel(limit);
+        let queue = NotifiedQueue {
+            inner: Arc::new(Mutex::new((sender, HashSet::new()))),
+        };
+        (queue, receiver)
+    }
+
+    /// Queue a feed unless it's already queued, returning false if the queue is full
+    fn push(&self, feed_url: &str) -> bool {
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        let (sender, queued) = &mut *inner;
+        if queued.contains(feed_url) {
+            return true;
+        }
+        if sender.try_send(String::from(feed_url)).is_err() {
+            return false;
+        }
+        queued.insert(String::from(feed_url));
+        true
+    }
+
+    /// Note that a feed was taken from the queue, so it can be queued again
+    fn taken(&self, feed_url: &str) {
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        inner.1.remove(feed_url);
+    }
+}
+
+/// Poll feeds as rssCloud servers announce updates, one at a time and as
+/// soon as the minimum fetch period and the server's caching hints allow
+async fn poll_notified_feeds(
+    db_writer: &DbWriter,
+    poll_options: &PollOptions,
+    notified_queue: NotifiedQueue,
+    mut notified_receiver: mpsc::Receiver<String>,
+) {
+    while let Some(feed_url) = notified_receiver.recv().await {
+        notified_queue.taken(&feed_url);
+        let subscription_url = feed_url.clone();
+        let overrides = match db_writer
+            .run(move |conn| db::find_subscription(conn, &subscription_url))
+            .await
+        {
+            Ok(Some(subscription)) if !local::is_allowed_subscription(&subscription) => {
+                log::warn!(
+                    "Refusing to poll {} - local feeds can't come from a subscription source",
+                    &feed_url
+                );
+                continue;
+            }
+            Ok(Some(subscription)) => PollOverrides::from(&subscription),
+            _ => PollOverrides::default(),
+        };
+        let mut options = poll_options.with_overrides(&overrides);
+        options.schedule = FetchSchedule::WhenAllowed;
+        match poll_one_feed(db_writer, &feed_url, &options).await {
+            Ok(FeedPollResult::Updated { .. }) => log::info!("Updated {}", &feed_url),
+            Ok(FeedPollResult::Skipped) => {
+                log::info!("Skipped notified feed {} - fetched too recently", &feed_url)
+            }
+            Ok(_) => log::info!("No updates for {}", &feed_url),
+            Err(error) => log::error!("Error polling notified feed {} - {:?}", &feed_url, error),
+        }
+    }
+}
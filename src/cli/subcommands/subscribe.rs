@@ -0,0 +1,61 @@
+use std::error::Error;
+
+use chrono::prelude::*;
+use clap::{App, Arg, ArgMatches};
+
+use super::read_lines;
+use feedspool::{db, models};
+
+pub const NAME: &str = "subscribe";
+
+pub fn app() -> App<'static> {
+    App::new(NAME)
+        .about("Subscribe to feeds")
+        .arg(
+            Arg::new("url")
+                .about("URL of feed")
+                .multiple(true)
+                .required_unless_present("feeds"),
+        )
+        .arg(
+            Arg::new("feeds")
+                .long("feeds")
+                .about("Filename of feeds list to import")
+                .takes_value(true),
+        )
+}
+
+pub async fn execute(matches: &ArgMatches, config: &config::Config) -> Result<(), Box<dyn Error>> {
+    let mut urls: Vec<String> = match matches.values_of("url") {
+        Some(values) => values.map(String::from).collect(),
+        None => Vec::new(),
+    };
+    if let Some(filename) = matches.value_of("feeds") {
+        for line in read_lines(filename)? {
+            let line = line?;
+            if !line.trim().is_empty() {
+                urls.push(String::from(line.trim()));
+            }
+        }
+    }
+
+    let now = Utc::now().to_rfc3339();
+    let conn = db::connect(&config)?;
+    for url in urls {
+        let upsert = models::SubscriptionUpsert {
+            url: &url,
+            title: None,
+            category: None,
+            source_id: None,
+            active: Some(true),
+            now: &now,
+        };
+        if db::upsert_subscription(&conn, &upsert)? {
+            log::info!("Subscribed to {}", url);
+        } else {
+            log::info!("Already subscribed to {}", url);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,175 @@
+use std::error::Error;
+use std::time::Duration;
+
+use clap::{App, AppSettings, Arg, ArgMatches};
+
+use feedspool::db;
+use feedspool::db::writer::DbWriter;
+use feedspool::feeds::options::PollOverrides;
+use feedspool::sources::{self, SourceSyncResult};
+
+use super::fetch;
+
+pub const NAME: &str = "subscriptions";
+
+const LIST: &str = "list";
+const SOURCES: &str = "sources";
+const ADD_SOURCE: &str = "add-source";
+const REMOVE_SOURCE: &str = "remove-source";
+const SYNC: &str = "sync";
+const SET: &str = "set";
+
+pub fn app() -> App<'static> {
+    App::new(NAME)
+        .about("Manage feed subscriptions")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(App::new(LIST).about("List subscribed feeds"))
+        .subcommand(App::new(SOURCES).about("List OPML subscription sources"))
+        .subcommand(
+            App::new(ADD_SOURCE)
+                .about("Add an OPML URL as a subscription source")
+                .arg(Arg::new("url").about("URL of OPML").required(true)),
+        )
+        .subcommand(
+            App::new(REMOVE_SOURCE)
+                .about("Remove an OPML subscription source")
+                .arg(Arg::new("url").about("URL of OPML").required(true)),
+        )
+        .subcommand(App::new(SYNC).about("Sync subscriptions from OPML sources"))
+        .subcommand(
+            App::new(SET)
+                .about("Set polling overrides for a subscription")
+                .arg(Arg::new("url").about("URL of feed").required(true))
+                .arg(
+                    Arg::new("overrides")
+                        .about(
+                            "Overrides as key=value, e.g. request_timeout=30 retain_src=true \
+                             header=X-Api-Key:abc123 - an empty value restores the global setting",
+                        )
+                        .multiple(true)
+                        .required(true),
+                ),
+        )
+}
+
+pub async fn execute(matches: &ArgMatches, config: &config::Config) -> Result<(), Box<dyn Error>> {
+    match matches.subcommand() {
+        Some((LIST, sub_m)) => list(&sub_m, &config),
+        Some((SOURCES, sub_m)) => list_sources(&sub_m, &config),
+        Some((ADD_SOURCE, sub_m)) => add_source(&sub_m, &config),
+        Some((REMOVE_SOURCE, sub_m)) => remove_source(&sub_m, &config),
+        Some((SYNC, _)) => {
+            let db_writer = DbWriter::new(&db::create_pool(config)?)?;
+            sync_sources(&config, &db_writer, &fetch::http_client(config)?).await
+        }
+        Some((SET, sub_m)) => set_overrides(&sub_m, &config),
+        _ => Ok(()),
+    }
+}
+
+fn list(_matches: &ArgMatches, config: &config::Config) -> Result<(), Box<dyn Error>> {
+    let conn = db::connect(&config)?;
+    for subscription in db::find_subscriptions(&conn)? {
+        let overrides = PollOverrides::from(&subscription);
+        if let Some(url) = subscription.url {
+            let mut line = url;
+            if !overrides.is_empty() {
+                line = format!("{} {}", line, overrides);
+            }
+            if let Some(reason) = &subscription.inactive_reason {
+                line = format!("# {} (inactive - {})", line, reason);
+            } else if subscription.active == Some(false) {
+                line = format!("# {} (inactive)", line);
+            } else if subscription.paused_at.is_some() {
+                line = format!("# {} (paused)", line);
+            }
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+fn set_overrides(matches: &ArgMatches, config: &config::Config) -> Result<(), Box<dyn Error>> {
+    let url = matches.value_of("url").unwrap();
+    let conn = db::connect(&config)?;
+    match db::find_subscription(&conn, url)? {
+        None => log::warn!("Not subscribed to {}", url),
+        Some(subscription) => {
+            let mut overrides = PollOverrides::from(&subscription);
+            if let Some(pairs) = matches.values_of("overrides") {
+                for pair in pairs {
+                    overrides.apply(pair)?;
+                }
+            }
+            db::update_subscription_overrides(&conn, url, &overrides)?;
+            log::info!("Updated overrides for {} - {}", url, overrides);
+        }
+    }
+    Ok(())
+}
+
+fn list_sources(_matches: &ArgMatches, config: &config::Config) -> Result<(), Box<dyn Error>> {
+    let conn = db::connect(&config)?;
+    for source in db::find_subscription_sources(&conn)? {
+        if let Some(url) = source.url {
+            println!("{}", url);
+        }
+    }
+    Ok(())
+}
+
+fn add_source(matches: &ArgMatches, config: &config::Config) -> Result<(), Box<dyn Error>> {
+    let url = matches.value_of("url").unwrap();
+    let conn = db::connect(&config)?;
+    if db::insert_subscription_source(&conn, url)? {
+        log::info!("Added subscription source {}", url);
+    } else {
+        log::info!("Subscription source already exists {}", url);
+    }
+    Ok(())
+}
+
+fn remove_source(matches: &ArgMatches, config: &config::Config) -> Result<(), Box<dyn Error>> {
+    let url = matches.value_of("url").unwrap();
+    let conn = db::connect(&config)?;
+    if db::delete_subscription_source(&conn, url)? {
+        log::info!("Removed subscription source {}", url);
+    } else {
+        log::warn!("No subscription source {}", url);
+    }
+    Ok(())
+}
+
+/// Sync subscriptions from all OPML sources, logging failures without giving up
+pub async fn sync_sources(
+    config: &config::Config,
+    db_writer: &DbWriter,
+    client: &reqwest::Client,
+) -> Result<(), Box<dyn Error>> {
+    let request_timeout = Duration::from_secs(config.get("fetch_request_timeout")?);
+    let subscription_sources = db_writer.run(db::find_subscription_sources).await?;
+    for source in subscription_sources {
+        if let Some(url) = source.url {
+            log::info!("Syncing subscriptions from {}", url);
+            match sources::sync_one_source(db_writer, client, &url, request_timeout).await {
+                Ok(SourceSyncResult::NotModified { .. }) => {
+                    log::info!("No updates for {}", url)
+                }
+                Ok(SourceSyncResult::Synced {
+                    total,
+                    added,
+                    deactivated,
+                    ..
+                }) => log::info!(
+                    "Synced {} feeds from {} ({} new, {} inactive)",
+                    total,
+                    url,
+                    added,
+                    deactivated
+                ),
+                Err(error) => log::error!("Error syncing {} - {:?}", url, error),
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,30 @@
+use std::error::Error;
+
+use clap::{App, Arg, ArgMatches};
+
+use feedspool::db;
+
+pub const NAME: &str = "unsubscribe";
+
+pub fn app() -> App<'static> {
+    App::new(NAME).about("Unsubscribe from feeds").arg(
+        Arg::new("url")
+            .about("URL of feed")
+            .multiple(true)
+            .required(true),
+    )
+}
+
+pub async fn execute(matches: &ArgMatches, config: &config::Config) -> Result<(), Box<dyn Error>> {
+    let conn = db::connect(&config)?;
+    if let Some(urls) = matches.values_of("url") {
+        for url in urls {
+            if db::delete_subscription(&conn, url)? {
+                log::info!("Unsubscribed from {}", url);
+            } else {
+                log::warn!("Not subscribed to {}", url);
+            }
+        }
+    }
+    Ok(())
+}
@@ -2,14 +2,18 @@ use chrono::prelude::*;
 use diesel::prelude::*;
 use diesel_migrations::embed_migrations;
 use std::collections::HashSet;
+use std::convert::TryFrom;
 use std::error::Error;
+use std::fmt;
 use std::hash::BuildHasher;
+use std::time::Duration;
 
 use diesel::{
     r2d2::{ConnectionManager, Pool},
     sqlite::SqliteConnection,
 };
 
+use crate::feeds::options::PollOverrides;
 use crate::feeds::result::{ConditionalGetData, FeedFetchResult, FeedPollError};
 
 use sha2::{Digest, Sha256};
@@ -17,6 +21,7 @@ use sha2::{Digest, Sha256};
 embed_migrations!("migrations");
 
 pub mod paginate_dsl;
+pub mod writer;
 
 pub type SqlitePool = Pool<ConnectionManager<SqliteConnection>>;
 
@@ -57,6 +62,121 @@ pub fn feed_id_from_url(url: &str) -> String {
     format!("{:x}", Sha256::new().chain(url).finalize())
 }
 
+/// Hash of a fetched body, to tell whether content has changed between fetches
+#[must_use]
+pub fn body_hash(body: &str) -> String {
+    format!("{:x}", Sha256::new().chain(body).finalize())
+}
+
+#[must_use]
+pub fn entry_id_from_guid(parent_feed_id: &str, guid: &str) -> String {
+    format!(
+        "{:x}",
+        Sha256::new().chain(parent_feed_id).chain(guid).finalize()
+    )
+}
+
+/// Merge a feed into another, as when a feed has moved to a new URL. Entries,
+/// history, and subscription are re-parented to the new feed id.
+///
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn merge_feed(
+    conn: &SqliteConnection,
+    from_url: &str,
+    to_url: &str,
+) -> Result<(), diesel::result::Error> {
+    use crate::schema::{entries, feed_history, feeds, subscriptions};
+
+    let from_id = feed_id_from_url(from_url);
+    let to_id = feed_id_from_url(to_url);
+    if from_id == to_id {
+        return Ok(());
+    }
+    let now = Utc::now().to_rfc3339();
+
+    conn.transaction(|| {
+        let old_entries = entries::table
+            .filter(entries::feed_id.eq(&from_id))
+            .select((entries::id, entries::guid, entries::json))
+            .load::<(Option<String>, Option<String>, Option<String>)>(conn)?;
+        for (old_entry_id, guid, json) in old_entries {
+            // Entries stored before guids were recorded still have the guid in their JSON
+            let guid = guid.or_else(|| {
+                json.and_then(|json| serde_json::from_str::<serde_json::Value>(&json).ok())
+                    .and_then(|value| value["id"].as_str().map(String::from))
+            });
+            let new_entry_id = guid.map_or_else(
+                || old_entry_id.clone().unwrap_or_default(),
+                |guid| entry_id_from_guid(&to_id, &guid),
+            );
+            let new_entry_exists = entries::table
+                .filter(entries::id.eq(&new_entry_id))
+                .count()
+                .get_result::<i64>(conn)?
+                > 0;
+            if new_entry_exists && Some(&new_entry_id) != old_entry_id.as_ref() {
+                diesel::delete(entries::table)
+                    .filter(entries::id.eq(&old_entry_id))
+                    .execute(conn)?;
+            } else {
+                diesel::update(entries::table)
+                    .filter(entries::id.eq(&old_entry_id))
+                    .set((entries::id.eq(&new_entry_id), entries::feed_id.eq(&to_id)))
+                    .execute(conn)?;
+            }
+        }
+
+        diesel::update(feed_history::table)
+            .filter(feed_history::feed_id.eq(&from_id))
+            .set(feed_history::feed_id.eq(&to_id))
+            .execute(conn)?;
+
+        let to_feed_exists = feeds::table
+            .filter(feeds::id.eq(&to_id))
+            .count()
+            .get_result::<i64>(conn)?
+            > 0;
+        if to_feed_exists {
+            diesel::delete(feeds::table)
+                .filter(feeds::id.eq(&from_id))
+                .execute(conn)?;
+        } else {
+            diesel::update(feeds::table)
+                .filter(feeds::id.eq(&from_id))
+                .set((
+                    feeds::id.eq(&to_id),
+                    feeds::url.eq(to_url),
+                    feeds::modified_at.eq(&now),
+                ))
+                .execute(conn)?;
+        }
+
+        let to_subscription_exists = subscriptions::table
+            .filter(subscriptions::id.eq(&to_id))
+            .count()
+            .get_result::<i64>(conn)?
+            > 0;
+        if to_subscription_exists {
+            diesel::delete(subscriptions::table)
+                .filter(subscriptions::id.eq(&from_id))
+                .execute(conn)?;
+        } else {
+            diesel::update(subscriptions::table)
+                .filter(subscriptions::id.eq(&from_id))
+                .set((
+                    subscriptions::id.eq(&to_id),
+                    subscriptions::url.eq(to_url),
+                    subscriptions::modified_at.eq(&now),
+                ))
+                .execute(conn)?;
+        }
+
+        Ok(())
+    })
+}
+
 /// # Errors
 ///
 /// Returns `diesel::result::Error` for any DB failure
@@ -107,60 +227,84 @@ pub fn upsert_feed(
     Ok(())
 }
 
+/// Insert or update an entry, returning whether it was new, updated or
+/// unchanged. An existing entry is left alone if its JSON is the same and
+/// it isn't defunct.
+///
 /// # Errors
 ///
 /// Returns `diesel::result::Error` for any DB failure
 pub fn upsert_entry(
     conn: &SqliteConnection,
     upsert: &crate::models::EntryUpsert,
-) -> Result<(), diesel::result::Error> {
-    use crate::models;
-    use crate::schema::entries::dsl::{entries, id};
+) -> Result<crate::models::EntryUpsertResult, diesel::result::Error> {
+    use crate::models::{self, EntryUpsertResult};
+    use crate::schema::entries::dsl::{defunct, entries, id, json};
 
-    let entry_exists = entries
+    let existing = entries
         .filter(id.eq(&upsert.id))
-        .count()
-        .get_result::<i64>(conn)?
-        > 0;
+        .select((json, defunct))
+        .first::<(Option<String>, Option<bool>)>(conn)
+        .optional()?;
 
-    if entry_exists {
+    if let Some((existing_json, existing_defunct)) = existing {
         log::trace!("Entry exists {}", &upsert.id);
-        if !&upsert.skip_update {
-            diesel::update(entries)
-                .filter(id.eq(&upsert.id))
-                .set(models::EntryUpdate {
-                    defunct: Some(false),
-                    json: Some(&upsert.json),
-                    title: Some(&upsert.title),
-                    link: Some(&upsert.link),
-                    summary: Some(&upsert.summary),
-                    content: Some(&upsert.content),
-                    published: Some(&upsert.published),
-                    updated: Some(&upsert.updated),
-                    modified_at: Some(&upsert.now),
-                })
-                .execute(conn)?;
+        let is_changed =
+            existing_defunct == Some(true) || existing_json.as_deref() != Some(upsert.json);
+        if upsert.skip_update || !is_changed {
+            return Ok(EntryUpsertResult::Unchanged);
         }
-    } else {
-        log::trace!("Entry new {}", &upsert.id);
-        diesel::insert_into(entries)
-            .values(models::EntryNew {
-                id: &upsert.id,
-                feed_id: &upsert.feed_id,
-                defunct: false,
-                json: &upsert.json,
-                title: &upsert.title,
-                link: &upsert.link,
-                summary: &upsert.summary,
-                content: &upsert.content,
-                published: &upsert.published,
-                updated: &upsert.updated,
-                modified_at: &upsert.now,
-                created_at: &upsert.now,
+        diesel::update(entries)
+            .filter(id.eq(&upsert.id))
+            .set(models::EntryUpdate {
+                guid: Some(&upsert.guid),
+                defunct: Some(false),
+                json: Some(&upsert.json),
+                title: Some(&upsert.title),
+                link: Some(&upsert.link),
+                summary: Some(&upsert.summary),
+                content: Some(&upsert.content),
+                published: Some(&upsert.published),
+                updated: Some(&upsert.updated),
+                modified_at: Some(&upsert.now),
             })
             .execute(conn)?;
+        return Ok(EntryUpsertResult::Updated);
     }
-    Ok(())
+
+    log::trace!("Entry new {}", &upsert.id);
+    diesel::insert_into(entries)
+        .values(models::EntryNew {
+            id: &upsert.id,
+            guid: &upsert.guid,
+            feed_id: &upsert.feed_id,
+            defunct: false,
+            json: &upsert.json,
+            title: &upsert.title,
+            link: &upsert.link,
+            summary: &upsert.summary,
+            content: &upsert.content,
+            published: &upsert.published,
+            updated: &upsert.updated,
+            modified_at: &upsert.now,
+            created_at: &upsert.now,
+        })
+        .execute(conn)?;
+    Ok(EntryUpsertResult::New)
+}
+
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn find_entries_for_feed(
+    conn: &SqliteConnection,
+    parent_feed_id: &str,
+) -> Result<Vec<crate::models::Entry>, diesel::result::Error> {
+    use crate::schema::entries::dsl::{entries, feed_id, published};
+    entries
+        .filter(feed_id.eq(parent_feed_id))
+        .order(published.desc())
+        .load::<crate::models::Entry>(conn)
 }
 
 /// # Errors
@@ -185,6 +329,7 @@ pub fn insert_feed_history(
     conn: &SqliteConnection,
     fetch: &FeedFetchResult,
     retain_src: bool,
+    not_before: Option<&str>,
 ) -> Result<(), FeedPollError> {
     let now = Utc::now().to_rfc3339();
     let history_id = &format!(
@@ -207,6 +352,9 @@ pub fn insert_feed_history(
                 etag: header_or_blank(&fetch.headers, reqwest::header::ETAG),
                 last_modified: header_or_blank(&fetch.headers, reqwest::header::LAST_MODIFIED),
                 created_at: &now,
+                not_before,
+                encoding: &fetch.encoding,
+                body_hash: &body_hash(&fetch.body),
             })
             .execute(conn)
         {
@@ -216,13 +364,17 @@ pub fn insert_feed_history(
     Ok(())
 }
 
+/// Record a failed fetch, with `attempt` counting from 1 for each try within a poll
+///
 /// # Errors
 ///
 /// Returns `FeedPollError::DatabaseError` for any DB failure
-pub fn insert_feed_history_error(
+pub fn insert_feed_history_error<E: fmt::Debug>(
     conn: &SqliteConnection,
     url: &str,
-    error: &FeedPollError,
+    error: &E,
+    not_before: Option<&str>,
+    attempt: usize,
 ) -> Result<(), FeedPollError> {
     let now = Utc::now().to_rfc3339();
     let feed_id = feed_id_from_url(&url);
@@ -237,6 +389,8 @@ pub fn insert_feed_history_error(
                 created_at: &now,
                 is_error: true,
                 error_text: format!("{:?}", &error).as_str(),
+                not_before,
+                attempt: i32::try_from(attempt).unwrap_or(i32::MAX),
             })
             .execute(conn)
         {
@@ -266,7 +420,7 @@ pub fn find_last_get_conditions(
     let feed_id = feed_id_from_url(feed_url);
     match feed_history::table
         .filter(feed_history::dsl::feed_id.eq(feed_id))
-        .filter(feed_history::dsl::status.eq("200"))
+        .filter(feed_history::dsl::status.eq_any(vec!["200", "226"]))
         .order(feed_history::dsl::created_at.desc())
         .select((feed_history::dsl::etag, feed_history::dsl::last_modified))
         .first::<(Option<String>, Option<String>)>(conn)
@@ -279,6 +433,20 @@ pub fn find_last_get_conditions(
     }
 }
 
+/// Find the body hash from the last successful fetch of a feed, if recorded
+pub fn find_last_body_hash(conn: &SqliteConnection, feed_url: &str) -> Option<String> {
+    use crate::schema::feed_history;
+    let feed_id = feed_id_from_url(feed_url);
+    feed_history::table
+        .filter(feed_history::dsl::feed_id.eq(feed_id))
+        .filter(feed_history::dsl::status.eq_any(vec!["200", "226"]))
+        .order(feed_history::dsl::created_at.desc())
+        .select(feed_history::dsl::body_hash)
+        .first::<Option<String>>(conn)
+        .ok()
+        .flatten()
+}
+
 pub fn find_last_fetch_time(conn: &SqliteConnection, feed_url: &str) -> Option<String> {
     use crate::schema::feed_history;
     let feed_id = feed_id_from_url(feed_url);
@@ -292,3 +460,609 @@ pub fn find_last_fetch_time(conn: &SqliteConnection, feed_url: &str) -> Option<S
         Ok(last_fetch_time) => last_fetch_time,
     }
 }
+
+/// Find the "not before" time recorded with the most recent fetch of a feed, if any.
+pub fn find_last_not_before(conn: &SqliteConnection, feed_url: &str) -> Option<String> {
+    use crate::schema::feed_history;
+    let feed_id = feed_id_from_url(feed_url);
+    match feed_history::table
+        .filter(feed_history::dsl::feed_id.eq(feed_id))
+        .order(feed_history::dsl::created_at.desc())
+        .select(feed_history::dsl::not_before)
+        .first::<Option<String>>(conn)
+    {
+        Err(_) => None,
+        Ok(not_before) => not_before,
+    }
+}
+
+/// Find publish times of a feed's most recent entries, newest first.
+///
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn find_recent_entry_published(
+    conn: &SqliteConnection,
+    feed_url: &str,
+    limit: i64,
+) -> Result<Vec<String>, diesel::result::Error> {
+    use crate::schema::entries::dsl::{entries, feed_id, published};
+    Ok(entries
+        .filter(feed_id.eq(feed_id_from_url(feed_url)))
+        .filter(published.ne(""))
+        .order(published.desc())
+        .select(published)
+        .limit(limit)
+        .load::<Option<String>>(conn)?
+        .into_iter()
+        .flatten()
+        .collect())
+}
+
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn find_subscriptions(
+    conn: &SqliteConnection,
+) -> Result<Vec<crate::models::Subscription>, diesel::result::Error> {
+    use crate::schema::subscriptions::dsl::{subscriptions, url};
+    subscriptions
+        .order(url.asc())
+        .load::<crate::models::Subscription>(conn)
+}
+
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn find_subscription(
+    conn: &SqliteConnection,
+    feed_url: &str,
+) -> Result<Option<crate::models::Subscription>, diesel::result::Error> {
+    use crate::schema::subscriptions::dsl::{id, subscriptions};
+    subscriptions
+        .filter(id.eq(feed_id_from_url(feed_url)))
+        .first::<crate::models::Subscription>(conn)
+        .optional()
+}
+
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn find_active_subscriptions(
+    conn: &SqliteConnection,
+) -> Result<Vec<crate::models::Subscription>, diesel::result::Error> {
+    use crate::schema::subscriptions::dsl::{active, paused_at, subscriptions, url};
+    subscriptions
+        .filter(active.eq(true))
+        .filter(paused_at.is_null())
+        .order(url.asc())
+        .load::<crate::models::Subscription>(conn)
+}
+
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn find_paused_subscriptions(
+    conn: &SqliteConnection,
+) -> Result<Vec<crate::models::Subscription>, diesel::result::Error> {
+    use crate::schema::subscriptions::dsl::{paused_at, subscriptions, url};
+    subscriptions
+        .filter(paused_at.is_not_null())
+        .order(url.asc())
+        .load::<crate::models::Subscription>(conn)
+}
+
+/// Pause polling a subscription, returning false if there was no such subscription.
+///
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn pause_subscription(
+    conn: &SqliteConnection,
+    feed_url: &str,
+    reason: &str,
+) -> Result<bool, diesel::result::Error> {
+    use crate::schema::subscriptions::dsl::{
+        id, modified_at, paused_at, paused_reason, subscriptions,
+    };
+    let now = Utc::now().to_rfc3339();
+    let count = diesel::update(subscriptions)
+        .filter(id.eq(feed_id_from_url(feed_url)))
+        .set((
+            paused_at.eq(&now),
+            paused_reason.eq(reason),
+            modified_at.eq(&now),
+        ))
+        .execute(conn)?;
+    Ok(count > 0)
+}
+
+/// Resume polling a paused subscription, returning false if there was no such paused subscription.
+/// Errors recorded before resuming no longer count toward pausing it again.
+///
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn resume_subscription(
+    conn: &SqliteConnection,
+    feed_url: &str,
+) -> Result<bool, diesel::result::Error> {
+    use crate::schema::subscriptions::dsl::{
+        id, modified_at, paused_at, paused_reason, resumed_at, subscriptions,
+    };
+    let now = Utc::now().to_rfc3339();
+    let count = diesel::update(subscriptions)
+        .filter(id.eq(feed_id_from_url(feed_url)))
+        .filter(paused_at.is_not_null())
+        .set((
+            paused_at.eq(None::<String>),
+            paused_reason.eq(None::<String>),
+            resumed_at.eq(&now),
+            modified_at.eq(&now),
+        ))
+        .execute(conn)?;
+    Ok(count > 0)
+}
+
+/// Count the errors at the head of a feed's fetch history, looking back at most
+/// `limit` fetches and ignoring any recorded before the subscription was last resumed.
+/// Retries within a poll aren't counted, so each failed poll counts once.
+///
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn count_consecutive_errors(
+    conn: &SqliteConnection,
+    feed_url: &str,
+    limit: usize,
+) -> Result<usize, diesel::result::Error> {
+    use crate::schema::{feed_history, subscriptions};
+    let feed_id = feed_id_from_url(feed_url);
+    let resumed_at = subscriptions::table
+        .filter(subscriptions::id.eq(&feed_id))
+        .select(subscriptions::resumed_at)
+        .first::<Option<String>>(conn)
+        .optional()?
+        .flatten()
+        .unwrap_or_else(|| String::from(""));
+    let is_errors = feed_history::table
+        .filter(feed_history::feed_id.eq(&feed_id))
+        .filter(feed_history::created_at.gt(&resumed_at))
+        .filter(
+            feed_history::attempt
+                .is_null()
+                .or(feed_history::attempt.le(1)),
+        )
+        .order(feed_history::created_at.desc())
+        .select(feed_history::is_error)
+        .limit(i64::try_from(limit).unwrap_or(i64::MAX))
+        .load::<Option<bool>>(conn)?;
+    Ok(is_errors
+        .iter()
+        .take_while(|is_error| **is_error == Some(true))
+        .count())
+}
+
+/// Subscribe to a feed URL, returning false if the subscription already existed.
+/// Other fields of an existing subscription are updated when supplied, except
+/// that its source never changes once added.
+///
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn upsert_subscription(
+    conn: &SqliteConnection,
+    upsert: &crate::models::SubscriptionUpsert,
+) -> Result<bool, diesel::result::Error> {
+    use crate::models;
+    use crate::schema::subscriptions::dsl::{id, subscriptions};
+
+    let subscription_id = feed_id_from_url(upsert.url);
+
+    let existing = subscriptions
+        .filter(id.eq(&subscription_id))
+        .first::<models::Subscription>(conn)
+        .optional()?;
+
+    if let Some(existing) = existing {
+        log::trace!("Subscription exists {}", &subscription_id);
+        // Only whoever added a feed, a source or by hand, may reactivate it,
+        // and not while it's paused or deactivated for a reason like being gone
+        let active = upsert.active.filter(|_| {
+            existing.source_id.as_deref() == upsert.source_id
+                && existing.paused_at.is_none()
+                && existing.inactive_reason.is_none()
+        });
+        if upsert.title.is_some() || upsert.category.is_some() || active.is_some() {
+            diesel::update(subscriptions)
+                .filter(id.eq(&subscription_id))
+                .set(models::SubscriptionUpdate {
+                    modified_at: Some(upsert.now),
+                    title: upsert.title,
+                    category: upsert.category,
+                    active,
+                })
+                .execute(conn)?;
+        }
+        return Ok(false);
+    }
+
+    log::trace!("Subscription new {}", &subscription_id);
+    diesel::insert_into(subscriptions)
+        .values(models::SubscriptionNew {
+            id: &subscription_id,
+            url: upsert.url,
+            created_at: upsert.now,
+            modified_at: upsert.now,
+            title: upsert.title,
+            category: upsert.category,
+            source_id: upsert.source_id,
+            active: upsert.active.unwrap_or(true),
+        })
+        .execute(conn)?;
+    Ok(true)
+}
+
+/// Stop polling a subscription for good, recording why, while keeping it
+/// and its overrides around. Returns false if there was no such subscription.
+///
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn deactivate_subscription(
+    conn: &SqliteConnection,
+    feed_url: &str,
+    reason: &str,
+) -> Result<bool, diesel::result::Error> {
+    use crate::schema::subscriptions::dsl::{
+        active, id, inactive_reason, modified_at, subscriptions,
+    };
+    let now = Utc::now().to_rfc3339();
+    let count = diesel::update(subscriptions)
+        .filter(id.eq(feed_id_from_url(feed_url)))
+        .set((
+            active.eq(false),
+            inactive_reason.eq(reason),
+            modified_at.eq(&now),
+        ))
+        .execute(conn)?;
+    Ok(count > 0)
+}
+
+/// Unsubscribe from a feed URL, returning false if there was no such subscription.
+///
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn delete_subscription(
+    conn: &SqliteConnection,
+    feed_url: &str,
+) -> Result<bool, diesel::result::Error> {
+    use crate::schema::subscriptions::dsl::{id, subscriptions};
+    let count = diesel::delete(subscriptions)
+        .filter(id.eq(feed_id_from_url(feed_url)))
+        .execute(conn)?;
+    Ok(count > 0)
+}
+
+/// Replace the polling overrides for a subscription, returning false if there was no such subscription.
+///
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn update_subscription_overrides(
+    conn: &SqliteConnection,
+    feed_url: &str,
+    overrides: &PollOverrides,
+) -> Result<bool, diesel::result::Error> {
+    use crate::models;
+    use crate::schema::subscriptions::dsl::{id, subscriptions};
+    let now = Utc::now().to_rfc3339();
+    let seconds = |duration: Option<Duration>| {
+        duration.and_then(|duration| i32::try_from(duration.as_secs()).ok())
+    };
+    let headers = crate::feeds::client::format_headers(&overrides.headers).join("\n");
+    let count = diesel::update(subscriptions)
+        .filter(id.eq(feed_id_from_url(feed_url)))
+        .set(models::SubscriptionOverridesUpdate {
+            modified_at: &now,
+            request_timeout: seconds(overrides.request_timeout),
+            min_fetch_period: seconds(overrides.min_fetch_period),
+            max_fetch_period: seconds(overrides.max_fetch_period),
+            retain_src: overrides.retain_src,
+            skip_entry_update: overrides.skip_entry_update,
+            headers: Some(headers.as_str()).filter(|headers| !headers.is_empty()),
+            credentials: overrides.credentials.as_deref(),
+        })
+        .execute(conn)?;
+    Ok(count > 0)
+}
+
+/// Mark subscriptions from a source inactive, if they were not seen in its latest sync.
+///
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn deactivate_unseen_source_subscriptions<S: BuildHasher>(
+    conn: &SqliteConnection,
+    parent_source_id: &str,
+    seen_subscription_ids: HashSet<String, S>,
+    now: &str,
+) -> Result<usize, diesel::result::Error> {
+    use crate::schema::subscriptions::dsl::{active, id, modified_at, source_id, subscriptions};
+    diesel::update(subscriptions)
+        .filter(
+            source_id
+                .eq(parent_source_id)
+                .and(active.eq(true))
+                .and(id.ne_all(seen_subscription_ids)),
+        )
+        .set((active.eq(false), modified_at.eq(now)))
+        .execute(conn)
+}
+
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn find_subscription_sources(
+    conn: &SqliteConnection,
+) -> Result<Vec<crate::models::SubscriptionSource>, diesel::result::Error> {
+    use crate::schema::subscription_sources::dsl::{subscription_sources, url};
+    subscription_sources
+        .order(url.asc())
+        .load::<crate::models::SubscriptionSource>(conn)
+}
+
+/// Add an OPML URL as a subscription source, returning false if it already existed.
+///
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn insert_subscription_source(
+    conn: &SqliteConnection,
+    source_url: &str,
+) -> Result<bool, diesel::result::Error> {
+    use crate::models;
+    use crate::schema::subscription_sources::dsl::{id, subscription_sources};
+
+    let now = Utc::now().to_rfc3339();
+    let source_id = feed_id_from_url(source_url);
+
+    let source_exists = subscription_sources
+        .filter(id.eq(&source_id))
+        .count()
+        .get_result::<i64>(conn)?
+        > 0;
+
+    if source_exists {
+        return Ok(false);
+    }
+
+    diesel::insert_into(subscription_sources)
+        .values(models::SubscriptionSourceNew {
+            id: &source_id,
+            url: source_url,
+            created_at: &now,
+            modified_at: &now,
+        })
+        .execute(conn)?;
+    Ok(true)
+}
+
+/// Remove a subscription source, returning false if there was no such source.
+/// Subscriptions synced from the source are kept, but no longer tied to it.
+///
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn delete_subscription_source(
+    conn: &SqliteConnection,
+    source_url: &str,
+) -> Result<bool, diesel::result::Error> {
+    use crate::schema::{subscription_sources, subscriptions};
+    let source_id = feed_id_from_url(source_url);
+    conn.transaction(|| {
+        diesel::update(subscriptions::table)
+            .filter(subscriptions::source_id.eq(&source_id))
+            .set(subscriptions::source_id.eq(None::<String>))
+            .execute(conn)?;
+        let count = diesel::delete(subscription_sources::table)
+            .filter(subscription_sources::id.eq(&source_id))
+            .execute(conn)?;
+        Ok(count > 0)
+    })
+}
+
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn find_feeds_with_subscriptions(
+    conn: &SqliteConnection,
+) -> Result<Vec<(crate::models::Feed, Option<crate::models::Subscription>)>, diesel::result::Error>
+{
+    use crate::schema::{feeds, subscriptions};
+    feeds::table
+        .left_join(subscriptions::table.on(feeds::id.eq(subscriptions::id)))
+        .order(feeds::title.asc())
+        .load::<(crate::models::Feed, Option<crate::models::Subscription>)>(conn)
+}
+
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn find_websub_subscription(
+    conn: &SqliteConnection,
+    subscription_id: &str,
+) -> Result<Option<crate::models::WebSubSubscription>, diesel::result::Error> {
+    use crate::schema::websub_subscriptions::dsl::{id, websub_subscriptions};
+    websub_subscriptions
+        .filter(id.eq(subscription_id))
+        .first::<crate::models::WebSubSubscription>(conn)
+        .optional()
+}
+
+/// Find a WebSub subscription by the secret token in its callback URL
+///
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn find_websub_subscription_by_callback(
+    conn: &SqliteConnection,
+    token: &str,
+) -> Result<Option<crate::models::WebSubSubscription>, diesel::result::Error> {
+    use crate::schema::websub_subscriptions::dsl::{callback_token, websub_subscriptions};
+    websub_subscriptions
+        .filter(callback_token.eq(token))
+        .first::<crate::models::WebSubSubscription>(conn)
+        .optional()
+}
+
+/// Record a pending WebSub subscription request for a feed, replacing any earlier request.
+///
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn upsert_websub_request(
+    conn: &SqliteConnection,
+    request: &crate::models::WebSubSubscriptionRequest,
+) -> Result<(), diesel::result::Error> {
+    use crate::models;
+    use crate::schema::websub_subscriptions::dsl::{id, websub_subscriptions};
+
+    let subscription_id = feed_id_from_url(request.feed_url);
+
+    let subscription_exists = websub_subscriptions
+        .filter(id.eq(&subscription_id))
+        .count()
+        .get_result::<i64>(conn)?
+        > 0;
+
+    if subscription_exists {
+        log::trace!("WebSub subscription exists {}", &subscription_id);
+        diesel::update(websub_subscriptions)
+            .filter(id.eq(&subscription_id))
+            .set(request)
+            .execute(conn)?;
+    } else {
+        log::trace!("WebSub subscription new {}", &subscription_id);
+        diesel::insert_into(websub_subscriptions)
+            .values(models::WebSubSubscriptionNew {
+                id: &subscription_id,
+                feed_url: request.feed_url,
+                hub_url: request.hub_url,
+                topic_url: request.topic_url,
+                secret: request.secret,
+                state: request.state,
+                created_at: request.modified_at,
+                modified_at: request.modified_at,
+                callback_token: request.callback_token,
+            })
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Record the outcome of a hub verifying a WebSub subscription, returning false if there was no such subscription.
+///
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn update_websub_state(
+    conn: &SqliteConnection,
+    subscription_id: &str,
+    new_state: &str,
+    new_lease_expires_at: Option<&str>,
+) -> Result<bool, diesel::result::Error> {
+    use crate::schema::websub_subscriptions::dsl::{
+        id, lease_expires_at, modified_at, state, websub_subscriptions,
+    };
+    let now = Utc::now().to_rfc3339();
+    let count = diesel::update(websub_subscriptions)
+        .filter(id.eq(subscription_id))
+        .set((
+            state.eq(new_state),
+            lease_expires_at.eq(new_lease_expires_at),
+            modified_at.eq(&now),
+        ))
+        .execute(conn)?;
+    Ok(count > 0)
+}
+
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn find_rsscloud_registration(
+    conn: &SqliteConnection,
+    registration_id: &str,
+) -> Result<Option<crate::models::RssCloudRegistration>, diesel::result::Error> {
+    use crate::schema::rsscloud_registrations::dsl::{id, rsscloud_registrations};
+    rsscloud_registrations
+        .filter(id.eq(registration_id))
+        .first::<crate::models::RssCloudRegistration>(conn)
+        .optional()
+}
+
+/// Record an rssCloud registration request for a feed, keeping the time of any earlier successful registration.
+///
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn upsert_rsscloud_request(
+    conn: &SqliteConnection,
+    feed_url: &str,
+    new_cloud_url: &str,
+) -> Result<(), diesel::result::Error> {
+    use crate::models;
+    use crate::schema::rsscloud_registrations::dsl::{
+        cloud_url, id, modified_at, rsscloud_registrations,
+    };
+
+    let registration_id = feed_id_from_url(feed_url);
+    let now = Utc::now().to_rfc3339();
+
+    let registration_exists = rsscloud_registrations
+        .filter(id.eq(&registration_id))
+        .count()
+        .get_result::<i64>(conn)?
+        > 0;
+
+    if registration_exists {
+        log::trace!("rssCloud registration exists {}", &registration_id);
+        diesel::update(rsscloud_registrations)
+            .filter(id.eq(&registration_id))
+            .set((cloud_url.eq(new_cloud_url), modified_at.eq(&now)))
+            .execute(conn)?;
+    } else {
+        log::trace!("rssCloud registration new {}", &registration_id);
+        diesel::insert_into(rsscloud_registrations)
+            .values(models::RssCloudRegistrationNew {
+                id: &registration_id,
+                feed_url,
+                cloud_url: new_cloud_url,
+                created_at: &now,
+                modified_at: &now,
+            })
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Record that a cloud accepted a registration for a feed.
+///
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn update_rsscloud_registered(
+    conn: &SqliteConnection,
+    registration_id: &str,
+) -> Result<(), diesel::result::Error> {
+    use crate::schema::rsscloud_registrations::dsl::{
+        id, modified_at, registered_at, rsscloud_registrations,
+    };
+    let now = Utc::now().to_rfc3339();
+    diesel::update(rsscloud_registrations)
+        .filter(id.eq(registration_id))
+        .set((registered_at.eq(&now), modified_at.eq(&now)))
+        .execute(conn)?;
+    Ok(())
+}
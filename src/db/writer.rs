@@ -0,0 +1,57 @@
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::sqlite::SqliteConnection;
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use super::SqlitePool;
+
+/// Runs database work for polling on the blocking thread pool, so that it
+/// doesn't hold up fetches in flight or requests to the web server. All the
+/// work goes through a single connection, one job at a time, since SQLite
+/// allows only one writer and concurrent writers would fail with `SQLITE_BUSY`.
+#[derive(Clone)]
+pub struct DbWriter {
+    conn: Arc<Mutex<PooledConnection<ConnectionManager<SqliteConnection>>>>,
+}
+
+impl fmt::Debug for DbWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DbWriter {{ .. }}")
+    }
+}
+
+impl DbWriter {
+    /// Take a connection from the pool to be used for all writes
+    ///
+    /// # Errors
+    ///
+    /// Will return `r2d2::Error` if no connection could be had from the pool
+    pub fn new(pool: &SqlitePool) -> Result<DbWriter, r2d2::Error> {
+        Ok(DbWriter {
+            conn: Arc::new(Mutex::new(pool.get()?)),
+        })
+    }
+
+    /// Run a job with the connection, once no other job is using it. Jobs
+    /// waiting at the same time may run in any order, not the order queued.
+    ///
+    /// # Panics
+    ///
+    /// Will resume any panic from the job
+    pub async fn run<F, T>(&self, job: F) -> T
+    where
+        F: FnOnce(&SqliteConnection) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let conn = self.conn.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let conn = conn.lock().unwrap_or_else(PoisonError::into_inner);
+            job(&conn)
+        })
+        .await;
+        match result {
+            Ok(result) => result,
+            Err(error) => std::panic::resume_unwind(error.into_panic()),
+        }
+    }
+}
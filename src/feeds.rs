@@ -4,141 +4,610 @@
 use chrono::prelude::*;
 use diesel::sqlite::SqliteConnection;
 use feed_rs::model::Entry;
-use feed_rs::parser;
-use sha2::{Digest, Sha256};
-use std::collections::HashSet;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::collections::{HashMap, HashSet};
 use std::time::Duration;
 
+pub mod caching;
+pub mod client;
+pub mod credentials;
+pub mod discovery;
+pub mod encoding;
+pub mod hosts;
+pub mod local;
+pub mod options;
 pub mod result;
+pub mod retry;
+pub mod schedule;
 
+use crate::db::writer::DbWriter;
 use crate::db::{
-    feed_id_from_url, find_last_fetch_time, find_last_get_conditions, insert_feed_history,
-    insert_feed_history_error, mark_old_entries_defunct, upsert_entry, upsert_feed,
+    body_hash, count_consecutive_errors, deactivate_subscription, entry_id_from_guid,
+    feed_id_from_url, find_entries_for_feed, find_last_body_hash, find_last_get_conditions,
+    insert_feed_history, insert_feed_history_error, mark_old_entries_defunct, merge_feed,
+    pause_subscription, upsert_entry, upsert_feed,
 };
-use result::{ConditionalGetData, FeedFetchResult, FeedPollError, FeedPollResult};
+use crate::{rsscloud, websub};
+use client::RequestOptions;
+use credentials::Credentials;
+use options::PollOptions;
+use result::{
+    ConditionalGetData, EntryCounts, EntryPreview, FeedFetchResult, FeedPollError, FeedPollPreview,
+    FeedPollResult,
+};
+use schedule::FetchSchedule;
 
 /// # Errors
 ///
 /// Will return Err for any failure while polling a feed
 pub async fn poll_one_feed(
-    conn: &SqliteConnection,
+    db: &DbWriter,
     url: &str,
-    request_timeout: Duration,
-    min_fetch_period: Duration,
-    retain_src: bool,
-    skip_entry_update: bool,
+    options: &PollOptions,
 ) -> Result<FeedPollResult, FeedPollError> {
+    let mut attempt = 1;
     let fetch_result = async {
-        if was_feed_recently_fetched(&conn, &url, min_fetch_period)? {
-            log::trace!("Skipped fetch for {} - min fetch period", &url);
-            return Ok(FeedPollResult::Skipped);
+        let feed_url = String::from(url);
+        let min_fetch_period = options.min_fetch_period;
+        let max_fetch_period = options.max_fetch_period;
+        let next_fetch_time = match options.schedule {
+            FetchSchedule::Now => None,
+            FetchSchedule::WhenDue => {
+                db.run(move |conn| {
+                    schedule::next_fetch_time(conn, &feed_url, min_fetch_period, max_fetch_period)
+                })
+                .await?
+            }
+            FetchSchedule::WhenAllowed => {
+                db.run(move |conn| schedule::earliest_fetch_time(conn, &feed_url, min_fetch_period))
+                    .await?
+            }
+        };
+        if let Some(next_fetch_time) = next_fetch_time {
+            if Utc::now() < next_fetch_time {
+                log::trace!(
+                    "Skipped fetch for {} - not due until {}",
+                    &url,
+                    next_fetch_time
+                );
+                return Ok(FeedPollResult::Skipped);
+            }
         }
-        let last_get_conditions = find_last_get_conditions(&conn, &url);
-        let mut fetch_result = fetch_feed(url, request_timeout, last_get_conditions).await?;
-        fetch_result = update_feed(&conn, fetch_result, skip_entry_update)?;
-        Ok(fetch_result)
+        let mut fetch_result = fetch_with_retries(db, &url, &options, &mut attempt).await?;
+        if let FeedPollResult::Fetched { fetch, .. } | FeedPollResult::NotModified { fetch } =
+            &mut fetch_result
+        {
+            if let Some(moved_to) = fetch.moved_to.clone() {
+                log::info!("Feed {} moved permanently to {}", &url, &moved_to);
+                let (from_url, to_url) = (String::from(url), moved_to.clone());
+                db.run(move |conn| merge_feed(conn, &from_url, &to_url))
+                    .await
+                    .map_err(FeedPollError::DatabaseError)?;
+                fetch.id = feed_id_from_url(&moved_to);
+                fetch.url = moved_to;
+            }
+        }
+        let mark_defunct = marks_defunct(&fetch_result);
+        let skip_entry_update = options.skip_entry_update;
+        db.run(move |conn| {
+            let fetch_result = detect_unchanged(conn, fetch_result);
+            update_feed(conn, fetch_result, skip_entry_update, mark_defunct)
+        })
+        .await
     };
+    let retain_src = options.retain_src;
+    let max_fetch_period = options.max_fetch_period;
     match fetch_result.await {
         Ok(fetch_result) => {
-            if let FeedPollResult::Updated { fetch, .. }
-            | FeedPollResult::NotModified { fetch, .. } = &fetch_result
-            {
-                insert_feed_history(&conn, &fetch, retain_src)?;
-            }
+            let fetch_result = db
+                .run(move |conn| {
+                    let fetch_and_ttl = match &fetch_result {
+                        FeedPollResult::Updated { fetch, feed, .. }
+                        | FeedPollResult::Unchanged { fetch, feed } => Some((fetch, feed.ttl)),
+                        FeedPollResult::NotModified { fetch } => Some((fetch, None)),
+                        _ => None,
+                    };
+                    if let Some((fetch, ttl)) = fetch_and_ttl {
+                        let not_before =
+                            caching::not_before(&fetch, ttl, Utc::now(), max_fetch_period)
+                                .map(|not_before| not_before.to_rfc3339());
+                        insert_feed_history(conn, &fetch, retain_src, not_before.as_deref())?;
+                    }
+                    Ok::<_, FeedPollError>(fetch_result)
+                })
+                .await?;
+            renew_push_subscriptions(db, &fetch_result, &options).await;
             Ok(fetch_result)
         }
         Err(error) => {
-            insert_feed_history_error(&conn, &url, &error)?;
-            Err(error)
+            let url = String::from(url);
+            let max_consecutive_errors = options.max_consecutive_errors;
+            db.run(move |conn| {
+                let not_before = match &error {
+                    FeedPollError::FetchFailed { fetch } => {
+                        caching::not_before(&fetch, None, Utc::now(), max_fetch_period)
+                            .map(|not_before| not_before.to_rfc3339())
+                    }
+                    _ => None,
+                };
+                insert_feed_history_error(conn, &url, &error, not_before.as_deref(), attempt)?;
+                handle_poll_error(conn, &url, &error, max_consecutive_errors)
+                    .map_err(FeedPollError::DatabaseError)?;
+                Err(error)
+            })
+            .await
+        }
+    }
+}
+
+/// Fetch and parse a feed, then work out what polling it would change in the
+/// database without writing anything. The fetch schedule is ignored and the
+/// fetch isn't retried, but it's conditional on the last fetch as usual, and
+/// entries count as updated only if their content has changed.
+///
+/// # Errors
+///
+/// Will return Err for any failure while fetching the feed or reading the database
+pub async fn preview_one_feed(
+    db: &DbWriter,
+    url: &str,
+    options: &PollOptions,
+) -> Result<FeedPollPreview, FeedPollError> {
+    let request_options = options.feed_request_options()?;
+    let feed_url = String::from(url);
+    let last_get_conditions = db
+        .run(move |conn| find_last_get_conditions(conn, &feed_url))
+        .await;
+    let fetch_result = options
+        .host_limiter
+        .limit(
+            url,
+            fetch_feed(
+                &options.client,
+                url,
+                options.request_timeout,
+                last_get_conditions,
+                &request_options,
+            ),
+        )
+        .await?;
+    let mark_defunct = marks_defunct(&fetch_result);
+    let skip_entry_update = options.skip_entry_update;
+    db.run(move |conn| {
+        let fetch_result = detect_unchanged(conn, fetch_result);
+        preview_update(conn, fetch_result, skip_entry_update, mark_defunct)
+    })
+    .await
+}
+
+/// A feed fetched with the same body as its last successful fetch can't have
+/// changed, so there's no need to update it or its entries. This catches
+/// unchanged feeds from servers that send neither `ETag` nor `Last-Modified`.
+fn detect_unchanged(conn: &SqliteConnection, fetch_result: FeedPollResult) -> FeedPollResult {
+    match &fetch_result {
+        FeedPollResult::Fetched { fetch, .. }
+            if find_last_body_hash(conn, &fetch.url).map_or(false, |last_body_hash| {
+                last_body_hash == body_hash(&fetch.body)
+            }) =>
+        {
+            log::trace!("Content unchanged since last fetch for {}", &fetch.url);
+            fetch_result.fetched_to_unchanged()
         }
+        _ => fetch_result,
     }
 }
 
-fn was_feed_recently_fetched(
+/// A delta response only holds new entries, so the rest aren't defunct
+fn marks_defunct(fetch_result: &FeedPollResult) -> bool {
+    match fetch_result {
+        FeedPollResult::Fetched { fetch, .. } => {
+            fetch.status_code() != Some(reqwest::StatusCode::IM_USED)
+        }
+        _ => true,
+    }
+}
+
+/// Compare a fetched feed's entries with those stored, as `update_feed` would
+fn preview_update(
     conn: &SqliteConnection,
+    fetch_result: FeedPollResult,
+    skip_entry_update: bool,
+    mark_defunct: bool,
+) -> Result<FeedPollPreview, FeedPollError> {
+    let mut preview = FeedPollPreview {
+        result: fetch_result,
+        new_entries: Vec::new(),
+        updated_entries: Vec::new(),
+        defunct_entries: Vec::new(),
+    };
+    if let FeedPollResult::Fetched { feed, fetch } = &preview.result {
+        let stored_entries =
+            find_entries_for_feed(conn, &fetch.id).map_err(FeedPollError::DatabaseError)?;
+        let stored_by_id: HashMap<&str, &crate::models::Entry> = stored_entries
+            .iter()
+            .filter_map(|stored| stored.id.as_deref().map(|id| (id, stored)))
+            .collect();
+
+        let mut seen_entry_ids = HashSet::new();
+        for entry in &feed.entries {
+            let id = entry_id_from_guid(&fetch.id, &entry.id);
+            if !seen_entry_ids.insert(id.clone()) {
+                continue;
+            }
+            let entry_preview = EntryPreview {
+                id,
+                guid: entry.id.clone(),
+                title: entry.title.as_ref().map(|title| title.content.clone()),
+            };
+            match stored_by_id.get(entry_preview.id.as_str()) {
+                None => preview.new_entries.push(entry_preview),
+                Some(stored) if !skip_entry_update => {
+                    let json = serde_json::to_string(&entry).unwrap_or_else(|_| String::from(""));
+                    if stored.defunct == Some(true) || stored.json.as_deref() != Some(&json) {
+                        preview.updated_entries.push(entry_preview);
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+
+        if mark_defunct {
+            preview.defunct_entries = stored_entries
+                .iter()
+                .filter(|stored| stored.defunct != Some(true))
+                .filter_map(|stored| {
+                    let id = stored.id.as_ref()?;
+                    if seen_entry_ids.contains(id) {
+                        return None;
+                    }
+                    Some(EntryPreview {
+                        id: id.clone(),
+                        guid: stored.guid.clone().unwrap_or_default(),
+                        title: stored.title.clone().filter(|title| !title.is_empty()),
+                    })
+                })
+                .collect();
+        }
+    }
+    Ok(preview)
+}
+
+/// Fetch a feed, retrying after a backoff as set by `options.retry` for as
+/// long as it fails transiently. Each failed attempt but the last is recorded
+/// in the feed history here, with `attempt` left counting the last.
+async fn fetch_with_retries(
+    db: &DbWriter,
     url: &str,
-    min_fetch_period: Duration,
-) -> Result<bool, FeedPollError> {
-    let now = Utc::now();
-    let min_fetch_duration =
-        chrono::Duration::from_std(min_fetch_period).map_err(FeedPollError::FetchTimeError)?;
-    let last_fetch_time = find_last_fetch_time(&conn, &url);
-    if let Some(last_fetch_time) = last_fetch_time {
-        if let Ok(last_fetch_time) = chrono::DateTime::parse_from_rfc3339(&last_fetch_time) {
-            if now < last_fetch_time + min_fetch_duration {
-                return Ok(true);
+    options: &PollOptions,
+    attempt: &mut usize,
+) -> Result<FeedPollResult, FeedPollError> {
+    let request_options = options.feed_request_options()?;
+    loop {
+        let feed_url = String::from(url);
+        let last_get_conditions = db
+            .run(move |conn| find_last_get_conditions(conn, &feed_url))
+            .await;
+        let result = options
+            .host_limiter
+            .limit(
+                url,
+                fetch_feed(
+                    &options.client,
+                    url,
+                    options.request_timeout,
+                    last_get_conditions,
+                    &request_options,
+                ),
+            )
+            .await;
+        match result {
+            Err(error) if options.retry.should_retry(*attempt, &error) => {
+                let delay = options.retry.delay(*attempt);
+                log::info!(
+                    "Attempt {} for {} failed, retrying in {:?} - {:?}",
+                    attempt,
+                    &url,
+                    delay,
+                    error
+                );
+                let (feed_url, failed_attempt) = (String::from(url), *attempt);
+                db.run(move |conn| {
+                    insert_feed_history_error(conn, &feed_url, &error, None, failed_attempt)
+                })
+                .await?;
+                tokio::time::delay_for(delay).await;
+                *attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Ask any WebSub hub or rssCloud server advertised by a polled feed to push
+/// or announce its updates. Failures are only logged, since polling goes on
+/// regardless.
+async fn renew_push_subscriptions(
+    db: &DbWriter,
+    fetch_result: &FeedPollResult,
+    options: &PollOptions,
+) {
+    let (fetch, feed) = match fetch_result {
+        FeedPollResult::Updated { fetch, feed, .. } | FeedPollResult::Unchanged { fetch, feed } => {
+            (fetch, Some(feed))
+        }
+        FeedPollResult::NotModified { fetch } => (fetch, None),
+        _ => return,
+    };
+    if let Some(websub_options) = &options.websub {
+        let hub_links = feed.and_then(|feed| websub::find_hub_links(fetch, feed));
+        if let Err(error) = websub::renew_subscription(
+            db,
+            &fetch.url,
+            hub_links,
+            websub_options,
+            &options.client,
+            options.request_timeout,
+        )
+        .await
+        {
+            log::warn!(
+                "WebSub subscription failed for {} - {:?}",
+                &fetch.url,
+                error
+            );
+        }
+    }
+    if let Some(rsscloud_options) = &options.rsscloud {
+        let cloud = feed.and_then(|_| rsscloud::Cloud::parse(&fetch.body));
+        if let Err(error) = rsscloud::renew_registration(
+            db,
+            &fetch.url,
+            cloud,
+            rsscloud_options,
+            &options.client,
+            options.request_timeout,
+        )
+        .await
+        {
+            log::warn!(
+                "rssCloud registration failed for {} - {:?}",
+                &fetch.url,
+                error
+            );
+        }
+    }
+}
+
+/// A feed that's gone for good is deactivated, keeping its subscription and
+/// overrides, while a feed that keeps failing is paused until resumed by hand.
+fn handle_poll_error(
+    conn: &SqliteConnection,
+    url: &str,
+    error: &FeedPollError,
+    max_consecutive_errors: usize,
+) -> Result<(), diesel::result::Error> {
+    if let FeedPollError::Gone { .. } = error {
+        if deactivate_subscription(&conn, &url, "feed is gone")? {
+            log::warn!("Deactivated {} - feed is gone", &url);
+        }
+    } else if max_consecutive_errors > 0 {
+        let error_count = count_consecutive_errors(&conn, &url, max_consecutive_errors)?;
+        if error_count >= max_consecutive_errors {
+            let reason = format!("{} consecutive errors", error_count);
+            if pause_subscription(&conn, &url, &reason)? {
+                log::warn!("Paused {} after {}", &url, &reason);
             }
         }
     }
-    Ok(false)
+    Ok(())
 }
 
 /// # Errors
 ///
 /// Will return `FeedPollError` for any failure while fetching a feed
-///
-/// # Panics
-///
-/// Shouldn't be any panics here
 pub async fn fetch_feed(
+    client: &reqwest::Client,
     url: &str,
     timeout_duration: Duration,
     last_get_conditions: Option<ConditionalGetData>,
+    request_options: &RequestOptions,
 ) -> Result<FeedPollResult, FeedPollError> {
-    let mut request = reqwest::Client::new().get(url).timeout(timeout_duration);
-    if let Some(last_get_conditions) = last_get_conditions {
-        if let Some(etag) = last_get_conditions.etag {
-            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
-        }
-        if let Some(last_modified) = last_get_conditions.last_modified {
-            request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
-        }
+    // With an ETag to go on, ask for only the entries new since the last
+    // fetch, from servers supporting RFC 3229 delta encoding
+    let has_etag = last_get_conditions
+        .as_ref()
+        .and_then(|last_get_conditions| last_get_conditions.etag.as_ref())
+        .map_or(false, |etag| !etag.is_empty());
+    let mut request_options = request_options.clone();
+    if has_etag {
+        request_options.headers.insert(
+            HeaderName::from_static("a-im"),
+            HeaderValue::from_static("feed"),
+        );
+    }
+    let fetch = fetch_url(
+        client,
+        url,
+        timeout_duration,
+        last_get_conditions,
+        &request_options,
+    )
+    .await?;
+    match fetch.status_code() {
+        Some(reqwest::StatusCode::OK | reqwest::StatusCode::IM_USED) => match fetch.parse_feed() {
+            Err(error) => {
+                if discovery::looks_like_html(&fetch) {
+                    let feed_links = discovery::find_feed_links(&fetch.body, &fetch.final_url);
+                    if !feed_links.is_empty() {
+                        return Err(FeedPollError::FeedsDiscovered { fetch, feed_links });
+                    }
+                }
+                Err(FeedPollError::ParseError { fetch, error })
+            }
+            Ok(feed) => Ok(FeedPollResult::Fetched { fetch, feed }),
+        },
+        Some(reqwest::StatusCode::NOT_MODIFIED) => Ok(FeedPollResult::NotModified { fetch }),
+        Some(reqwest::StatusCode::GONE) => Err(FeedPollError::Gone { fetch }),
+        _ => Err(FeedPollError::FetchFailed { fetch }),
     }
+}
+
+const MAX_REDIRECTS: usize = 10;
+
+/// Perform a conditional GET for a URL, without interpreting the response status.
+/// Redirects are followed here rather than by reqwest, so that a chain of
+/// only permanent redirects can be reported as the URL having moved. Local
+/// files, standard input and commands are read as if fetched from the web.
+///
+/// # Errors
+///
+/// Will return `FeedPollError` for any failure while making the request or reading the response
+pub async fn fetch_url(
+    client: &reqwest::Client,
+    url: &str,
+    timeout_duration: Duration,
+    last_get_conditions: Option<ConditionalGetData>,
+    request_options: &RequestOptions,
+) -> Result<FeedFetchResult, FeedPollError> {
+    if local::is_local_source(url) {
+        return local::fetch_local(url, timeout_duration, last_get_conditions).await;
+    }
+
+    let mut final_url = String::from(url);
+    let mut moved_to = None;
+    let mut only_permanent_redirects = true;
+    let mut redirect_count = 0;
+    let response = loop {
+        let same_host = is_same_host(url, &final_url);
+        let credentials = request_options.credentials.as_ref().filter(|_| same_host);
+        let no_headers = HeaderMap::new();
+        let headers = if same_host {
+            &request_options.headers
+        } else {
+            &no_headers
+        };
+        let response = send_request(
+            client,
+            &final_url,
+            timeout_duration,
+            last_get_conditions.as_ref(),
+            headers,
+            credentials,
+        )
+        .await?;
+        match redirect_location(&final_url, &response) {
+            Some(location) if redirect_count < MAX_REDIRECTS => {
+                only_permanent_redirects = only_permanent_redirects
+                    && (response.status() == reqwest::StatusCode::MOVED_PERMANENTLY
+                        || response.status() == reqwest::StatusCode::PERMANENT_REDIRECT);
+                if only_permanent_redirects {
+                    moved_to = Some(location.clone());
+                }
+                log::trace!("Redirected from {} to {}", &final_url, &location);
+                final_url = location;
+                redirect_count += 1;
+            }
+            _ => break response,
+        }
+    };
 
-    match request.send().await {
+    let response_status = response.status();
+    let headers = response.headers().clone();
+    match response.bytes().await {
         Err(error) => {
             if error.is_timeout() {
                 Err(FeedPollError::Timedout(error))
-            } else if error.is_status() && error.status().unwrap() == reqwest::StatusCode::NOT_FOUND
-            {
-                Err(FeedPollError::NotFound(error))
             } else {
                 Err(FeedPollError::FetchError(error))
             }
         }
-        Ok(response) => {
-            let response_status = response.status();
-            let headers = response.headers().clone();
-            let body = response.text().await;
-            match body {
-                Err(error) => {
-                    if error.is_timeout() {
-                        Err(FeedPollError::Timedout(error))
-                    } else {
-                        Err(FeedPollError::FetchError(error))
-                    }
-                }
-                Ok(body) => {
-                    let fetch = FeedFetchResult {
-                        id: feed_id_from_url(&url),
-                        url: String::from(url),
-                        status: String::from(response_status.as_str()),
-                        headers,
-                        body,
-                    };
-                    match response_status {
-                        reqwest::StatusCode::OK => match parser::parse(fetch.body.as_bytes()) {
-                            Err(error) => Err(FeedPollError::ParseError { fetch, error }),
-                            Ok(feed) => Ok(FeedPollResult::Fetched { fetch, feed }),
-                        },
-                        reqwest::StatusCode::NOT_MODIFIED => {
-                            Ok(FeedPollResult::NotModified { fetch })
-                        }
-                        _ => Err(FeedPollError::FetchFailed { fetch }),
-                    }
-                }
-            }
+        Ok(bytes) => {
+            let content_type = headers
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok());
+            let (body, encoding) = encoding::decode_body(&bytes, content_type);
+            Ok(FeedFetchResult {
+                id: feed_id_from_url(&url),
+                url: String::from(url),
+                final_url,
+                moved_to: moved_to.filter(|moved_to| moved_to != url),
+                status: String::from(response_status.as_str()),
+                headers,
+                body,
+                encoding,
+            })
+        }
+    }
+}
+
+async fn send_request(
+    client: &reqwest::Client,
+    url: &str,
+    timeout_duration: Duration,
+    last_get_conditions: Option<&ConditionalGetData>,
+    headers: &HeaderMap,
+    credentials: Option<&Credentials>,
+) -> Result<reqwest::Response, FeedPollError> {
+    let mut request = client
+        .get(url)
+        .timeout(timeout_duration)
+        .headers(headers.clone());
+    if let Some(credentials) = credentials {
+        request = credentials.apply(request);
+    }
+    if let Some(last_get_conditions) = last_get_conditions {
+        // Feed history records a missing header as blank
+        if let Some(etag) = last_get_conditions
+            .etag
+            .as_ref()
+            .filter(|etag| !etag.is_empty())
+        {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = last_get_conditions
+            .last_modified
+            .as_ref()
+            .filter(|last_modified| !last_modified.is_empty())
+        {
+            request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+    request.send().await.map_err(|error| {
+        if error.is_timeout() {
+            FeedPollError::Timedout(error)
+        } else if error.status() == Some(reqwest::StatusCode::NOT_FOUND) {
+            FeedPollError::NotFound(error)
+        } else {
+            FeedPollError::FetchError(error)
+        }
+    })
+}
+
+fn is_same_host(url: &str, other_url: &str) -> bool {
+    match (url::Url::parse(url), url::Url::parse(other_url)) {
+        (Ok(url), Ok(other_url)) => {
+            url.host_str() == other_url.host_str()
+                && url.port_or_known_default() == other_url.port_or_known_default()
         }
+        _ => false,
+    }
+}
+
+fn redirect_location(url: &str, response: &reqwest::Response) -> Option<String> {
+    match response.status() {
+        reqwest::StatusCode::MOVED_PERMANENTLY
+        | reqwest::StatusCode::FOUND
+        | reqwest::StatusCode::SEE_OTHER
+        | reqwest::StatusCode::TEMPORARY_REDIRECT
+        | reqwest::StatusCode::PERMANENT_REDIRECT => {
+            let location = response.headers().get(reqwest::header::LOCATION)?;
+            let location = url::Url::parse(url)
+                .ok()?
+                .join(location.to_str().ok()?)
+                .ok()?;
+            Some(String::from(location))
+        }
+        _ => None,
     }
 }
 
@@ -153,10 +622,14 @@ fn clamp_future_date_to_now<'a>(
     now
 }
 
-fn update_feed(
+/// Store a fetched feed and its entries. Entries missing from the feed are
+/// marked defunct, unless `mark_defunct` is false because the feed content
+/// is known to be partial.
+pub(crate) fn update_feed(
     conn: &SqliteConnection,
     fetch_result: FeedPollResult,
     skip_entry_update: bool,
+    mark_defunct: bool,
 ) -> Result<FeedPollResult, FeedPollError> {
     use crate::models;
 
@@ -164,10 +637,18 @@ fn update_feed(
         let now = Utc::now();
 
         let mut seen_entry_ids = HashSet::new();
+        let mut entry_counts = EntryCounts::default();
         let mut last_entry_published: Option<DateTime<Utc>> = None;
         for entry in &feed.entries {
             match update_entry(&conn, &fetch.id, &entry, skip_entry_update) {
-                Ok(entry_id) => seen_entry_ids.insert(entry_id),
+                Ok((entry_id, upsert_result)) => {
+                    match upsert_result {
+                        models::EntryUpsertResult::New => entry_counts.new += 1,
+                        models::EntryUpsertResult::Updated => entry_counts.updated += 1,
+                        models::EntryUpsertResult::Unchanged => {}
+                    }
+                    seen_entry_ids.insert(entry_id);
+                }
                 Err(error) => return Err(fetch_result.fetched_to_update_error(error)),
             };
             if let Some(entry_published) = &entry.published {
@@ -180,8 +661,10 @@ fn update_feed(
             }
         }
 
-        if let Err(error) = mark_old_entries_defunct(&conn, &fetch.id, seen_entry_ids) {
-            return Err(fetch_result.fetched_to_update_error(error));
+        if mark_defunct {
+            if let Err(error) = mark_old_entries_defunct(&conn, &fetch.id, seen_entry_ids) {
+                return Err(fetch_result.fetched_to_update_error(error));
+            }
         }
 
         match upsert_feed(
@@ -210,7 +693,7 @@ fn update_feed(
             },
         ) {
             Err(error) => Err(fetch_result.fetched_to_update_error(error)),
-            Ok(_) => Ok(fetch_result.fetched_to_updated()),
+            Ok(_) => Ok(fetch_result.fetched_to_updated(entry_counts)),
         }
     } else {
         Ok(fetch_result)
@@ -222,22 +705,17 @@ fn update_entry(
     parent_feed_id: &str,
     entry: &Entry,
     skip_update: bool,
-) -> Result<String, diesel::result::Error> {
+) -> Result<(String, crate::models::EntryUpsertResult), diesel::result::Error> {
     use crate::models;
     let now = Utc::now();
-    let id = format!(
-        "{:x}",
-        Sha256::new()
-            .chain(&parent_feed_id)
-            .chain(&entry.id)
-            .finalize()
-    );
-    upsert_entry(
+    let id = entry_id_from_guid(parent_feed_id, &entry.id);
+    let upsert_result = upsert_entry(
         &conn,
         &models::EntryUpsert {
             skip_update,
             now: &now.to_rfc3339(),
             id: &id,
+            guid: &entry.id,
             feed_id: &parent_feed_id,
             json: &serde_json::to_string(&entry).unwrap_or_else(|_| String::from("")),
             published: &entry.published.map_or(String::from(""), |dt| {
@@ -270,5 +748,5 @@ fn update_entry(
             ),
         },
     )?;
-    Ok(id)
+    Ok((id, upsert_result))
 }
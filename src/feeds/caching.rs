@@ -0,0 +1,178 @@
+use chrono::prelude::*;
+use std::time::Duration;
+use xml::reader::{EventReader, XmlEvent};
+
+use super::result::FeedFetchResult;
+
+/// Work out the earliest time a feed should be fetched again, from HTTP
+/// caching headers and the feed's own `<ttl>`, `<skipHours>` and `<skipDays>`.
+/// Freshness hints and any `Retry-After` on a 429 or 503 asking us to back
+/// off are capped at `max_fetch_period`.
+#[must_use]
+pub fn not_before(
+    fetch: &FeedFetchResult,
+    ttl: Option<u32>,
+    now: DateTime<Utc>,
+    max_fetch_period: Duration,
+) -> Option<DateTime<Utc>> {
+    let max_lifetime = chrono::Duration::from_std(max_fetch_period).ok()?;
+    if let Some(reqwest::StatusCode::TOO_MANY_REQUESTS | reqwest::StatusCode::SERVICE_UNAVAILABLE) =
+        fetch.status_code()
+    {
+        let delay = retry_after(&fetch.headers, now)?;
+        return now
+            .checked_add_signed(capped(delay, max_lifetime))
+            .filter(|earliest| *earliest > now);
+    }
+    let ttl = ttl.map(|ttl| chrono::Duration::minutes(i64::from(ttl)));
+    let lifetime = match (freshness_lifetime(&fetch.headers, now), ttl) {
+        (Some(lifetime), Some(ttl)) => Some(lifetime.max(ttl)),
+        (lifetime, ttl) => lifetime.or(ttl),
+    };
+    let mut earliest = now;
+    if let Some(lifetime) = lifetime {
+        earliest = now.checked_add_signed(capped(lifetime, max_lifetime))?;
+    }
+    let earliest = SkipTimes::parse(&fetch.body).next_allowed(earliest);
+    if earliest > now {
+        Some(earliest)
+    } else {
+        None
+    }
+}
+
+/// How long a response stays fresh, from `Cache-Control: max-age` less any
+/// `Age`, or failing that from `Expires` relative to `Date`
+fn freshness_lifetime(
+    headers: &reqwest::header::HeaderMap,
+    now: DateTime<Utc>,
+) -> Option<chrono::Duration> {
+    if let Some(max_age) = cache_control_max_age(headers) {
+        let age = header_str(headers, reqwest::header::AGE)
+            .and_then(|age| age.trim().parse::<i64>().ok())
+            .unwrap_or(0);
+        return Some(header_seconds(max_age.saturating_sub(age)));
+    }
+    let expires = parse_http_date(header_str(headers, reqwest::header::EXPIRES)?)?;
+    let date = header_str(headers, reqwest::header::DATE)
+        .and_then(parse_http_date)
+        .unwrap_or(now);
+    Some(expires - date)
+}
+
+fn cache_control_max_age(headers: &reqwest::header::HeaderMap) -> Option<i64> {
+    headers
+        .get_all(reqwest::header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find_map(|directive| {
+            let mut parts = directive.splitn(2, '=');
+            let name = parts.next()?.trim();
+            let value = parts.next()?.trim().trim_matches('"');
+            if name.eq_ignore_ascii_case("max-age") {
+                value.parse::<i64>().ok()
+            } else {
+                None
+            }
+        })
+}
+
+/// Parse `Retry-After`, which is either a number of seconds or an HTTP date,
+/// into how long to wait from now
+fn retry_after(
+    headers: &reqwest::header::HeaderMap,
+    now: DateTime<Utc>,
+) -> Option<chrono::Duration> {
+    let value = header_str(headers, reqwest::header::RETRY_AFTER)?.trim();
+    match value.parse::<i64>() {
+        Ok(seconds) => Some(header_seconds(seconds)),
+        Err(_) => parse_http_date(value).map(|date| date - now),
+    }
+}
+
+/// A number of seconds taken from a header as a duration, with negative
+/// values treated as zero and values too large for a duration saturated
+fn header_seconds(seconds: i64) -> chrono::Duration {
+    chrono::Duration::seconds(seconds.clamp(0, i64::MAX / 1000))
+}
+
+/// A lifetime or delay kept between zero and the given maximum
+fn capped(duration: chrono::Duration, max: chrono::Duration) -> chrono::Duration {
+    duration.clamp(chrono::Duration::zero(), max)
+}
+
+fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(value.trim())
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+fn header_str(
+    headers: &reqwest::header::HeaderMap,
+    name: reqwest::header::HeaderName,
+) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Hours (in GMT) and days during which an RSS channel asks not to be fetched
+#[derive(Debug, Default, PartialEq)]
+pub struct SkipTimes {
+    pub hours: Vec<u32>,
+    pub days: Vec<Weekday>,
+}
+
+impl SkipTimes {
+    /// Find `<skipHours>` and `<skipDays>` in an RSS document. Anything that
+    /// fails to parse is ignored.
+    #[must_use]
+    pub fn parse(body: &str) -> SkipTimes {
+        let mut skip_times = SkipTimes::default();
+        if !body.contains("<skip") {
+            return skip_times;
+        }
+        let mut path: Vec<String> = Vec::new();
+        for event in EventReader::new(body.as_bytes()) {
+            match event {
+                Ok(XmlEvent::StartElement { name, .. }) => path.push(name.local_name),
+                Ok(XmlEvent::EndElement { .. }) => {
+                    path.pop();
+                }
+                Ok(XmlEvent::Characters(text)) => {
+                    let parent = path.len().checked_sub(2).and_then(|idx| path.get(idx));
+                    match (parent.map(String::as_str), path.last().map(String::as_str)) {
+                        (Some("skipHours"), Some("hour")) => {
+                            if let Ok(hour) = text.trim().parse::<u32>() {
+                                // Some feeds count hours 1-24 rather than 0-23
+                                skip_times.hours.push(hour % 24);
+                            }
+                        }
+                        (Some("skipDays"), Some("day")) => {
+                            if let Ok(day) = text.trim().parse::<Weekday>() {
+                                skip_times.days.push(day);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                Err(_) => break,
+                _ => {}
+            }
+        }
+        skip_times
+    }
+
+    /// Find the first time from the given time onward that isn't skipped.
+    /// If every hour of the week is skipped, the skip times are ignored.
+    #[must_use]
+    pub fn next_allowed(&self, from: DateTime<Utc>) -> DateTime<Utc> {
+        let mut time = from;
+        for _ in 0..24 * 7 {
+            if !self.hours.contains(&time.hour()) && !self.days.contains(&time.weekday()) {
+                return time;
+            }
+            time = time.date().and_hms(time.hour(), 0, 0) + chrono::Duration::hours(1);
+        }
+        from
+    }
+}
@@ -0,0 +1,95 @@
+#![allow(clippy::module_name_repetitions)]
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+
+use super::credentials::Credentials;
+
+/// Settings for the HTTP client used for all outgoing requests
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClientOptions {
+    /// User-Agent sent with every request, if set
+    pub user_agent: Option<String>,
+    /// Proxy for all requests, e.g. `http://`, `https://` or `socks5://` URLs.
+    /// If not set, any proxy configured in the environment is used.
+    pub proxy: Option<String>,
+    /// Headers sent with every request
+    pub headers: HeaderMap,
+}
+
+impl ClientOptions {
+    /// Build a client with these options, to be shared by every request in a
+    /// run so that connections and TLS sessions are reused. The client
+    /// doesn't follow redirects, leaving that to `fetch_url`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `reqwest::Error` for an invalid proxy URL
+    pub fn build(&self) -> Result<reqwest::Client, reqwest::Error> {
+        let mut builder = reqwest::Client::builder()
+            .default_headers(self.headers.clone())
+            .redirect(reqwest::redirect::Policy::none());
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+        }
+        builder.build()
+    }
+}
+
+/// Settings for requests to a single feed, on top of those of the client
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RequestOptions {
+    /// Extra headers, replacing any client headers of the same name. Like
+    /// credentials, these are only sent to the host originally requested.
+    pub headers: HeaderMap,
+    /// Credentials sent only to the host of the URL originally requested,
+    /// never to other hosts it redirects to
+    pub credentials: Option<Credentials>,
+}
+
+/// Combine two sets of headers, with any in `extra` replacing those of the same name
+#[must_use]
+pub fn merge_headers(base: &HeaderMap, extra: &HeaderMap) -> HeaderMap {
+    let mut merged = base.clone();
+    for (name, value) in extra {
+        merged.insert(name.clone(), value.clone());
+    }
+    merged
+}
+
+/// Parse a header from `Name: Value` form, returning None if either is invalid
+#[must_use]
+pub fn parse_header(line: &str) -> Option<(HeaderName, HeaderValue)> {
+    let mut parts = line.splitn(2, ':');
+    let name = HeaderName::from_bytes(parts.next()?.trim().as_bytes()).ok()?;
+    let value = HeaderValue::from_str(parts.next()?.trim()).ok()?;
+    Some((name, value))
+}
+
+/// Whether a header carries a secret whose value shouldn't be shown, like
+/// `Authorization`, `Cookie` or anything ending in `-Token` or `-Key`
+#[must_use]
+pub fn is_sensitive_header(name: &HeaderName) -> bool {
+    let name = name.as_str();
+    name == "authorization"
+        || name == "proxy-authorization"
+        || name == "cookie"
+        || name.ends_with("-token")
+        || name.ends_with("-key")
+}
+
+/// Format headers as `Name: Value` lines, skipping any values that aren't text
+#[must_use]
+pub fn format_headers(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .iter()
+        .filter_map(|(name, value)| {
+            value
+                .to_str()
+                .ok()
+                .map(|value| format!("{}: {}", name, value))
+        })
+        .collect()
+}
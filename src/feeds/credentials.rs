@@ -0,0 +1,105 @@
+#![allow(clippy::module_name_repetitions)]
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Prefix for environment variables holding credentials, e.g.
+/// `APP_CREDENTIALS_GITLAB_TOKEN` for a token named `gitlab`
+const ENV_PREFIX: &str = "APP_CREDENTIALS_";
+
+/// HTTP credentials for a private feed - a bearer token, or else a username
+/// and password for basic auth. Debug output never includes the secrets.
+#[derive(Clone, Default, Deserialize, PartialEq)]
+pub struct Credentials {
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub token: Option<String>,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Credentials {{ .. }}")
+    }
+}
+
+impl Credentials {
+    /// Add these credentials to a request
+    pub fn apply(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match (&self.token, &self.username) {
+            (Some(token), _) => request.bearer_auth(token),
+            (None, Some(username)) => request.basic_auth(username, self.password.as_ref()),
+            (None, None) => request,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.token.is_none() && self.username.is_none()
+    }
+}
+
+/// Named credentials, kept apart from feed lists and the database so that
+/// subscriptions only ever refer to them by name. Credentials are looked up
+/// first in a TOML file of tables like `[name]` with `token` or `username`
+/// and `password` keys, then in environment variables like
+/// `APP_CREDENTIALS_NAME_TOKEN` or `APP_CREDENTIALS_NAME_USERNAME`.
+#[derive(Default)]
+pub struct CredentialStore {
+    credentials: HashMap<String, Credentials>,
+}
+
+impl fmt::Debug for CredentialStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CredentialStore")
+            .field("names", &self.credentials.keys().collect::<Vec<&String>>())
+            .finish()
+    }
+}
+
+impl CredentialStore {
+    /// Load credentials from a TOML file, if it exists
+    ///
+    /// # Errors
+    ///
+    /// Will return Err if the file can't be read or parsed
+    pub fn load(path: &str) -> Result<CredentialStore, Box<dyn Error>> {
+        if !Path::new(path).exists() {
+            return Ok(CredentialStore::default());
+        }
+        Ok(CredentialStore {
+            credentials: toml::from_str(&fs::read_to_string(path)?)?,
+        })
+    }
+
+    /// Find credentials by name, from the file or else the environment
+    #[must_use]
+    pub fn find(&self, name: &str) -> Option<Credentials> {
+        if let Some(credentials) = self.credentials.get(name) {
+            return Some(credentials.clone());
+        }
+        let env_name: String = name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let var = |key: &str| std::env::var(format!("{}{}_{}", ENV_PREFIX, env_name, key)).ok();
+        let credentials = Credentials {
+            username: var("USERNAME"),
+            password: var("PASSWORD"),
+            token: var("TOKEN"),
+        };
+        if credentials.is_empty() {
+            None
+        } else {
+            Some(credentials)
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use scraper::{Html, Selector};
+use std::time::Duration;
+use url::Url;
+
+use super::client::RequestOptions;
+use super::fetch_url;
+use super::result::{FeedFetchResult, FeedPollError};
+
+const FEED_MEDIA_TYPES: &[&str] = &[
+    "application/rss+xml",
+    "application/atom+xml",
+    "application/feed+json",
+];
+
+/// A feed advertised by an HTML page
+#[derive(Debug, PartialEq)]
+pub struct FeedLink {
+    pub url: String,
+    pub media_type: Option<String>,
+    pub title: Option<String>,
+}
+
+/// Fetch a URL and list the feeds it offers. If the URL is itself a feed,
+/// that is the only candidate. Otherwise, it's treated as an HTML page and
+/// searched for `<link rel="alternate">` feed links.
+///
+/// # Errors
+///
+/// Will return `FeedPollError` for any failure while fetching the URL
+pub async fn discover_feeds(
+    client: &reqwest::Client,
+    url: &str,
+    timeout_duration: Duration,
+) -> Result<Vec<FeedLink>, FeedPollError> {
+    let fetch = fetch_url(
+        client,
+        url,
+        timeout_duration,
+        None,
+        &RequestOptions::default(),
+    )
+    .await?;
+    if fetch.status_code() != Some(reqwest::StatusCode::OK) {
+        return Err(FeedPollError::FetchFailed { fetch });
+    }
+    if let Ok(feed) = fetch.parse_feed() {
+        return Ok(vec![FeedLink {
+            url: fetch.final_url,
+            media_type: None,
+            title: feed.title.map(|title| title.content),
+        }]);
+    }
+    Ok(find_feed_links(&fetch.body, &fetch.final_url))
+}
+
+/// Guess whether a fetch produced an HTML page, based on its content type or
+/// the start of its body
+#[must_use]
+pub fn looks_like_html(fetch: &FeedFetchResult) -> bool {
+    let content_type_is_html = fetch
+        .headers
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| value.to_lowercase().contains("html"));
+    let body_start = fetch
+        .body
+        .trim_start()
+        .chars()
+        .take(64)
+        .collect::<String>()
+        .to_lowercase();
+    content_type_is_html
+        || body_start.starts_with("<!doctype html")
+        || body_start.starts_with("<html")
+}
+
+/// Find feed links in an HTML page, resolving relative URLs against the page
+/// URL or its `<base href>`
+///
+/// # Panics
+///
+/// Shouldn't be any panics here
+#[must_use]
+pub fn find_feed_links(html: &str, page_url: &str) -> Vec<FeedLink> {
+    let document = Html::parse_document(html);
+    let page_url = Url::parse(page_url).ok();
+    let base_url = document
+        .select(&Selector::parse("base[href]").unwrap())
+        .next()
+        .and_then(|element| element.value().attr("href"))
+        .and_then(|href| match &page_url {
+            Some(page_url) => page_url.join(href).ok(),
+            None => Url::parse(href).ok(),
+        })
+        .or(page_url);
+
+    let mut feed_links: Vec<FeedLink> = Vec::new();
+    for element_ref in document.select(&Selector::parse("link[rel][href]").unwrap()) {
+        let element = element_ref.value();
+        let is_alternate = element.attr("rel").map_or(false, |rel| {
+            rel.split_whitespace()
+                .any(|rel| rel.eq_ignore_ascii_case("alternate"))
+        });
+        let media_type = element
+            .attr("type")
+            .map(|media_type| media_type.trim().to_lowercase())
+            .filter(|media_type| FEED_MEDIA_TYPES.contains(&media_type.as_str()));
+        if !is_alternate || media_type.is_none() {
+            continue;
+        }
+        let href = element.attr("href").unwrap_or("").trim();
+        let url = match &base_url {
+            Some(base_url) => base_url.join(href).map(String::from),
+            None => Url::parse(href).map(String::from),
+        };
+        if let Ok(url) = url {
+            if !feed_links.iter().any(|feed_link| feed_link.url == url) {
+                feed_links.push(FeedLink {
+                    url,
+                    media_type,
+                    title: element.attr("title").map(String::from),
+                });
+            }
+        }
+    }
+    feed_links
+}
@@ -0,0 +1,155 @@
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+
+/// How far into a body to look for an XML declaration
+const XML_DECLARATION_SCAN_LIMIT: usize = 1024;
+
+/// Decode a response body to text, returning the name of the encoding used.
+/// As in RFC 7303, a byte order mark wins over everything else, then the
+/// charset in the `Content-Type` header, then the encoding named in the XML
+/// declaration, and finally UTF-8.
+#[must_use]
+pub fn decode_body(bytes: &[u8], content_type: Option<&str>) -> (String, String) {
+    let encoding = content_type
+        .and_then(http_charset_encoding)
+        .or_else(|| xml_declaration_encoding(bytes))
+        .unwrap_or(UTF_8);
+    // decode() checks for a byte order mark first, overriding the given encoding
+    let (text, encoding, _) = encoding.decode(bytes);
+    (text.into_owned(), String::from(encoding.name()))
+}
+
+/// Find the encoding named by `<?xml ... encoding="..."?>` at the start of
+/// a body. Since the declaration was found by reading the body as ASCII, any
+/// claim that it's UTF-16 is ignored.
+fn xml_declaration_encoding(bytes: &[u8]) -> Option<&'static Encoding> {
+    let head = &bytes[..bytes.len().min(XML_DECLARATION_SCAN_LIMIT)];
+    declared_encoding(&String::from_utf8_lossy(head))
+        .filter(|encoding| *encoding != UTF_16LE && *encoding != UTF_16BE)
+}
+
+fn declared_encoding(text: &str) -> Option<&'static Encoding> {
+    let declaration = text.trim_start_matches('\u{feff}').trim_start();
+    if !declaration.starts_with("<?xml") {
+        return None;
+    }
+    let declaration = &declaration[..declaration.find("?>")?];
+    let value = declaration[declaration.find("encoding")? + "encoding".len()..]
+        .trim_start()
+        .strip_prefix('=')?
+        .trim_start();
+    let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let label = &value[1..];
+    let label = &label[..label.find(quote)?];
+    Encoding::for_label(label.trim().as_bytes())
+}
+
+/// Find the encoding named by the charset parameter of a `Content-Type` header
+fn http_charset_encoding(content_type: &str) -> Option<&'static Encoding> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let mut parts = param.splitn(2, '=');
+        let name = parts.next()?.trim();
+        let value = parts.next()?.trim().trim_matches('"');
+        if name.eq_ignore_ascii_case("charset") {
+            Encoding::for_label(value.as_bytes())
+        } else {
+            None
+        }
+    })
+}
+
+/// Prepare decoded text for an XML parser, by dropping any XML declaration
+/// naming an encoding other than UTF-8 - which the text no longer is in
+#[must_use]
+pub fn utf8_document(text: &str) -> &str {
+    match declared_encoding(text) {
+        Some(encoding) if encoding != UTF_8 => {
+            let declaration_end = text.find("?>").map_or(0, |idx| idx + "?>".len());
+            &text[declaration_end..]
+        }
+        _ => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::WINDOWS_1252;
+
+    const LATIN1_DECLARED: &[u8] =
+        b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><rss><title>caf\xe9</title></rss>";
+
+    #[test]
+    fn test_declared_encoding() {
+        assert_eq!(
+            declared_encoding("<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><rss/>"),
+            Some(WINDOWS_1252)
+        );
+        assert_eq!(
+            declared_encoding("\u{feff} <?xml version='1.0' encoding = 'utf-8' ?><rss/>"),
+            Some(UTF_8)
+        );
+        assert_eq!(declared_encoding("<?xml version=\"1.0\"?><rss/>"), None);
+        assert_eq!(declared_encoding("<rss encoding=\"utf-8\"/>"), None);
+        assert_eq!(
+            declared_encoding("<?xml version=\"1.0\" encoding=\"bogus\"?>"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_http_charset_encoding() {
+        assert_eq!(
+            http_charset_encoding("application/rss+xml; charset=ISO-8859-1"),
+            Some(WINDOWS_1252)
+        );
+        assert_eq!(
+            http_charset_encoding("text/xml;CHARSET=\"utf-8\""),
+            Some(UTF_8)
+        );
+        assert_eq!(http_charset_encoding("application/xml"), None);
+        assert_eq!(http_charset_encoding("charset=utf-8"), None);
+    }
+
+    #[test]
+    fn test_decode_body_bom() {
+        let mut bytes = vec![0xff, 0xfe];
+        for unit in "<rss>caf\u{e9}</rss>".encode_utf16() {
+            bytes.extend_from_slice(&unit.to_le_bytes());
+        }
+        let (text, encoding) = decode_body(&bytes, Some("text/xml; charset=iso-8859-1"));
+        assert_eq!(text, "<rss>caf\u{e9}</rss>");
+        assert_eq!(encoding, UTF_16LE.name());
+    }
+
+    #[test]
+    fn test_decode_body_declaration_only() {
+        let (text, encoding) = decode_body(LATIN1_DECLARED, Some("application/rss+xml"));
+        assert!(text.contains("caf\u{e9}"));
+        assert_eq!(encoding, WINDOWS_1252.name());
+    }
+
+    #[test]
+    fn test_decode_body_header_only() {
+        let (text, encoding) = decode_body(
+            b"<rss><title>caf\xe9</title></rss>",
+            Some("text/xml; charset=iso-8859-1"),
+        );
+        assert!(text.contains("caf\u{e9}"));
+        assert_eq!(encoding, WINDOWS_1252.name());
+    }
+
+    #[test]
+    fn test_decode_body_header_wins_over_declaration() {
+        let bytes = "<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><rss>caf\u{e9}</rss>";
+        let (text, encoding) = decode_body(bytes.as_bytes(), Some("text/xml; charset=utf-8"));
+        assert!(text.contains("caf\u{e9}"));
+        assert_eq!(encoding, UTF_8.name());
+    }
+
+    #[test]
+    fn test_decode_body_default() {
+        let (text, encoding) = decode_body("<rss>caf\u{e9}</rss>".as_bytes(), None);
+        assert!(text.contains("caf\u{e9}"));
+        assert_eq!(encoding, UTF_8.name());
+    }
+}
@@ -0,0 +1,113 @@
+#![allow(clippy::module_name_repetitions)]
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::time::Instant;
+
+/// Politeness limits for requests to a single host
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct HostLimits {
+    /// Maximum number of requests in flight to the host at once
+    pub concurrency_limit: usize,
+    /// Minimum seconds between starting requests to the host
+    pub request_delay: u64,
+}
+
+impl Default for HostLimits {
+    fn default() -> Self {
+        HostLimits {
+            concurrency_limit: 2,
+            request_delay: 1,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct HostState {
+    semaphore: Semaphore,
+    request_delay: Duration,
+    next_request: Mutex<Option<Instant>>,
+}
+
+/// Shared across a fetch run to keep requests to each host within its limits.
+/// Limits configured for a domain also cover its subdomains, which then share
+/// the domain's limits - e.g. limits for `substack.com` apply to all
+/// `*.substack.com` feeds together.
+#[derive(Debug, Default)]
+pub struct HostLimiter {
+    default_limits: HostLimits,
+    host_limits: HashMap<String, HostLimits>,
+    hosts: Mutex<HashMap<String, Arc<HostState>>>,
+}
+
+impl HostLimiter {
+    #[must_use]
+    pub fn new(default_limits: HostLimits, host_limits: HashMap<String, HostLimits>) -> Self {
+        HostLimiter {
+            default_limits,
+            host_limits: host_limits
+                .into_iter()
+                .map(|(host, limits)| (host.to_lowercase(), limits))
+                .collect(),
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Run a request to a URL once the limits for its host allow. URLs
+    /// without a host, like local files, aren't limited.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if a lock on host state was poisoned by another panic
+    pub async fn limit<F: Future>(&self, url: &str, request: F) -> F::Output {
+        let host = match self.host_state(url) {
+            Some(host) => host,
+            None => return request.await,
+        };
+        let _permit = host.semaphore.acquire().await;
+        let wait = {
+            let now = Instant::now();
+            let mut next_request = host.next_request.lock().unwrap();
+            let start = next_request.map_or(now, |next_request| next_request.max(now));
+            next_request.replace(start + host.request_delay);
+            start - now
+        };
+        if wait > Duration::from_secs(0) {
+            log::trace!("Delaying request to {} for {:?}", url, wait);
+            tokio::time::delay_for(wait).await;
+        }
+        request.await
+    }
+
+    fn host_state(&self, url: &str) -> Option<Arc<HostState>> {
+        let host = url::Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_lowercase))
+            .filter(|host| !host.is_empty())?;
+        let (key, limits) = self
+            .host_limits
+            .iter()
+            .filter(|(domain, _)| host == **domain || host.ends_with(&format!(".{}", domain)))
+            // The most specific domain wins, e.g. feeds.example.com over example.com
+            .max_by_key(|(domain, _)| domain.len())
+            .map_or((host.clone(), &self.default_limits), |(domain, limits)| {
+                (domain.clone(), limits)
+            });
+        let mut hosts = self.hosts.lock().unwrap();
+        let host_state = hosts
+            .entry(key)
+            .or_insert_with(|| {
+                Arc::new(HostState {
+                    semaphore: Semaphore::new(limits.concurrency_limit.max(1)),
+                    request_delay: Duration::from_secs(limits.request_delay),
+                    next_request: Mutex::new(None),
+                })
+            })
+            .clone();
+        Some(host_state)
+    }
+}
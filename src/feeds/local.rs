@@ -0,0 +1,181 @@
+use chrono::prelude::*;
+use std::io;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+
+use super::encoding;
+use super::result::{ConditionalGetData, FeedFetchResult, FeedPollError};
+use crate::db::feed_id_from_url;
+
+/// Feed "URL" for a document read from standard input
+pub const STDIN_URL: &str = "-";
+const FILE_PREFIX: &str = "file:";
+const EXEC_PREFIX: &str = "exec:";
+
+/// Whether a feed URL names a local file, standard input or a command
+/// whose output is a feed, rather than something on the web
+#[must_use]
+pub fn is_local_source(url: &str) -> bool {
+    url == STDIN_URL || url.starts_with(FILE_PREFIX) || url.starts_with(EXEC_PREFIX)
+}
+
+/// Whether a subscription may be polled. Local sources are only allowed for
+/// subscriptions added from the command line or a local feeds file, never for
+/// those synced from a subscription source - whoever controls the source could
+/// otherwise run commands or read files here.
+#[must_use]
+pub fn is_allowed_subscription(subscription: &crate::models::Subscription) -> bool {
+    subscription.source_id.is_none() || !subscription.url.as_deref().map_or(false, is_local_source)
+}
+
+/// Read a feed document from a local source, as if it had been fetched.
+/// Files report their modification time as `Last-Modified`, so an unchanged
+/// file comes back as 304 Not Modified. Commands are run with `sh -c` and
+/// killed, along with any processes they started, if they take longer than
+/// the request timeout.
+///
+/// # Errors
+///
+/// Will return `FeedPollError` if the source can't be read or a command fails
+pub async fn fetch_local(
+    url: &str,
+    timeout_duration: Duration,
+    last_get_conditions: Option<ConditionalGetData>,
+) -> Result<FeedFetchResult, FeedPollError> {
+    let mut headers = reqwest::header::HeaderMap::new();
+    let bytes = if url == STDIN_URL {
+        let mut bytes = Vec::new();
+        tokio::io::stdin()
+            .read_to_end(&mut bytes)
+            .await
+            .map_err(FeedPollError::ReadError)?;
+        bytes
+    } else if let Some(command) = url.strip_prefix(EXEC_PREFIX) {
+        run_command(command, timeout_duration).await?
+    } else {
+        let path = url::Url::parse(url)
+            .ok()
+            .and_then(|url| url.to_file_path().ok())
+            .ok_or_else(|| {
+                FeedPollError::ReadError(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid file URL {}", url),
+                ))
+            })?;
+        let modified: DateTime<Utc> = tokio::fs::metadata(&path)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .map_err(FeedPollError::ReadError)?
+            .into();
+        let last_modified = modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+        let is_unmodified = last_get_conditions
+            .and_then(|last_get_conditions| last_get_conditions.last_modified)
+            .map_or(false, |last_modified_before| {
+                last_modified_before == last_modified
+            });
+        if let Ok(value) = reqwest::header::HeaderValue::from_str(&last_modified) {
+            headers.insert(reqwest::header::LAST_MODIFIED, value);
+        }
+        if is_unmodified {
+            return Ok(local_fetch_result(
+                url,
+                reqwest::StatusCode::NOT_MODIFIED,
+                headers,
+                &[],
+            ));
+        }
+        tokio::fs::read(&path)
+            .await
+            .map_err(FeedPollError::ReadError)?
+    };
+    Ok(local_fetch_result(
+        url,
+        reqwest::StatusCode::OK,
+        headers,
+        &bytes,
+    ))
+}
+
+async fn run_command(command: &str, timeout_duration: Duration) -> Result<Vec<u8>, FeedPollError> {
+    let mut shell = Command::new("sh");
+    shell
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    start_process_group(&mut shell);
+    let child = shell.spawn().map_err(FeedPollError::ReadError)?;
+    let child_id = child.id();
+    let output = if let Ok(output) =
+        tokio::time::timeout(timeout_duration, child.wait_with_output()).await
+    {
+        output.map_err(FeedPollError::ReadError)?
+    } else {
+        kill_process_group(child_id);
+        return Err(FeedPollError::ReadError(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("command timed out after {:?}", timeout_duration),
+        )));
+    };
+    if !output.status.success() {
+        return Err(FeedPollError::CommandFailed {
+            status: output.status.to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        });
+    }
+    Ok(output.stdout)
+}
+
+/// Run a command in its own process group, so that anything the shell starts
+/// can be killed along with it on timeout
+#[cfg(unix)]
+fn start_process_group(command: &mut Command) {
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setpgid(0, 0) == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        });
+    }
+}
+
+#[cfg(not(unix))]
+fn start_process_group(_command: &mut Command) {}
+
+#[cfg(unix)]
+fn kill_process_group(child_id: u32) {
+    #[allow(clippy::cast_possible_wrap)]
+    let process_group = child_id as libc::pid_t;
+    unsafe {
+        libc::kill(-process_group, libc::SIGKILL);
+    }
+}
+
+/// Elsewhere only the shell itself is killed, by `kill_on_drop`
+#[cfg(not(unix))]
+fn kill_process_group(_child_id: u32) {}
+
+fn local_fetch_result(
+    url: &str,
+    status: reqwest::StatusCode,
+    headers: reqwest::header::HeaderMap,
+    bytes: &[u8],
+) -> FeedFetchResult {
+    let (body, encoding) = encoding::decode_body(bytes, None);
+    FeedFetchResult {
+        id: feed_id_from_url(url),
+        url: String::from(url),
+        final_url: String::from(url),
+        moved_to: None,
+        status: String::from(status.as_str()),
+        headers,
+        body,
+        encoding,
+    }
+}
@@ -0,0 +1,298 @@
+#![allow(clippy::module_name_repetitions)]
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::header::HeaderMap;
+
+use super::client::{self, RequestOptions};
+use super::credentials::CredentialStore;
+use super::hosts::HostLimiter;
+use super::result::FeedPollError;
+use super::retry::RetryPolicy;
+use super::schedule::FetchSchedule;
+use crate::models;
+use crate::rsscloud::RssCloudOptions;
+use crate::websub::WebSubOptions;
+
+/// Settings that control how a single feed is polled
+#[derive(Clone, Debug)]
+pub struct PollOptions {
+    pub request_timeout: Duration,
+    pub min_fetch_period: Duration,
+    pub max_fetch_period: Duration,
+    pub retain_src: bool,
+    pub skip_entry_update: bool,
+    /// Pause the subscription after this many consecutive errors, 0 to never pause
+    pub max_consecutive_errors: usize,
+    /// Retries within a poll for fetches that fail transiently
+    pub retry: RetryPolicy,
+    /// HTTP client shared by all requests, so connections are reused
+    pub client: reqwest::Client,
+    /// Extra headers sent only when fetching this feed
+    pub headers: HeaderMap,
+    /// Name of the credentials to use when fetching this feed, if any
+    pub credentials: Option<String>,
+    /// Where named credentials are found
+    pub credential_store: Arc<CredentialStore>,
+    /// Per-host politeness limits, shared by all feeds polled in a run
+    pub host_limiter: Arc<HostLimiter>,
+    /// Subscribe to WebSub hubs advertised by feeds, if set
+    pub websub: Option<WebSubOptions>,
+    /// Register with rssCloud servers advertised by feeds, if set
+    pub rsscloud: Option<RssCloudOptions>,
+    /// When to fetch the feed, whether due or not
+    pub schedule: FetchSchedule,
+}
+
+impl PollOptions {
+    /// Apply per-feed overrides on top of these options
+    #[must_use]
+    pub fn with_overrides(&self, overrides: &PollOverrides) -> PollOptions {
+        PollOptions {
+            request_timeout: overrides.request_timeout.unwrap_or(self.request_timeout),
+            min_fetch_period: overrides.min_fetch_period.unwrap_or(self.min_fetch_period),
+            max_fetch_period: overrides.max_fetch_period.unwrap_or(self.max_fetch_period),
+            retain_src: overrides.retain_src.unwrap_or(self.retain_src),
+            skip_entry_update: overrides
+                .skip_entry_update
+                .unwrap_or(self.skip_entry_update),
+            max_consecutive_errors: self.max_consecutive_errors,
+            retry: self.retry.clone(),
+            client: self.client.clone(),
+            headers: client::merge_headers(&self.headers, &overrides.headers),
+            credentials: overrides
+                .credentials
+                .clone()
+                .or_else(|| self.credentials.clone()),
+            credential_store: self.credential_store.clone(),
+            host_limiter: self.host_limiter.clone(),
+            websub: self.websub.clone(),
+            rsscloud: self.rsscloud.clone(),
+            schedule: self.schedule,
+        }
+    }
+
+    /// Request settings for fetching this feed, with its own headers and credentials
+    ///
+    /// # Errors
+    ///
+    /// Will return `FeedPollError::CredentialsNotFound` if the named credentials don't exist
+    pub fn feed_request_options(&self) -> Result<RequestOptions, FeedPollError> {
+        let credentials = match &self.credentials {
+            Some(name) => Some(
+                self.credential_store
+                    .find(name)
+                    .ok_or_else(|| FeedPollError::CredentialsNotFound(name.clone()))?,
+            ),
+            None => None,
+        };
+        Ok(RequestOptions {
+            headers: self.headers.clone(),
+            credentials,
+        })
+    }
+}
+
+/// Per-feed overrides of global `PollOptions`, where None means use the global setting
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PollOverrides {
+    pub request_timeout: Option<Duration>,
+    pub min_fetch_period: Option<Duration>,
+    pub max_fetch_period: Option<Duration>,
+    pub retain_src: Option<bool>,
+    pub skip_entry_update: Option<bool>,
+    pub headers: HeaderMap,
+    /// Name of credentials from the credentials file or environment
+    pub credentials: Option<String>,
+}
+
+/// Keys of the overrides accepted by `PollOverrides::apply`
+const OVERRIDE_KEYS: [&str; 7] = [
+    "request_timeout",
+    "min_fetch_period",
+    "max_fetch_period",
+    "retain_src",
+    "skip_entry_update",
+    "credentials",
+    "header",
+];
+
+#[derive(Debug)]
+pub struct InvalidOverride(pub String);
+impl fmt::Display for InvalidOverride {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid feed override: {}", self.0)
+    }
+}
+impl Error for InvalidOverride {}
+
+impl PollOverrides {
+    /// Apply a `key=value` override, e.g. `request_timeout=30` or `retain_src=true`.
+    /// Durations are in seconds, up to `i32::MAX`. An empty value clears the
+    /// override. Headers are added one at a time with `header=Name:Value`, and
+    /// `header=` clears them all. Private feeds refer to credentials by name,
+    /// e.g. `credentials=gitlab`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidOverride` for an unknown key or unparseable value
+    pub fn apply(&mut self, pair: &str) -> Result<(), InvalidOverride> {
+        let invalid = || InvalidOverride(String::from(pair));
+        let mut parts = pair.splitn(2, '=');
+        let key = parts.next().unwrap_or("").trim();
+        let value = parts.next().ok_or_else(invalid)?.trim();
+        let seconds = || -> Result<Option<Duration>, InvalidOverride> {
+            if value.is_empty() {
+                return Ok(None);
+            }
+            // Stored as a 32-bit integer, so anything larger can't be saved
+            value
+                .parse::<i32>()
+                .ok()
+                .and_then(|secs| u64::try_from(secs).ok())
+                .map(|secs| Some(Duration::from_secs(secs)))
+                .ok_or_else(invalid)
+        };
+        let flag = || -> Result<Option<bool>, InvalidOverride> {
+            if value.is_empty() {
+                return Ok(None);
+            }
+            value.parse::<bool>().map(Some).map_err(|_| invalid())
+        };
+        match key {
+            "request_timeout" => self.request_timeout = seconds()?,
+            "min_fetch_period" => self.min_fetch_period = seconds()?,
+            "max_fetch_period" => self.max_fetch_period = seconds()?,
+            "retain_src" => self.retain_src = flag()?,
+            "skip_entry_update" => self.skip_entry_update = flag()?,
+            "credentials" if value.is_empty() => self.credentials = None,
+            "credentials" => self.credentials = Some(String::from(value)),
+            "header" if value.is_empty() => self.headers.clear(),
+            "header" => {
+                let (name, value) = client::parse_header(value).ok_or_else(invalid)?;
+                self.headers.insert(name, value);
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(())
+    }
+
+    /// Parse a line from a feeds list file: a feed URL followed by optional
+    /// whitespace-separated `key=value` overrides. Each value runs up to the
+    /// next ` key=`, so header values may contain spaces, e.g.
+    /// `header=Authorization:Bearer abc123`. Returns None for blank lines
+    /// and comments starting with `#`. An `exec:` command runs to the end of the
+    /// line, since it may contain whitespace, and so can't have overrides.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidOverride` for any invalid override
+    pub fn parse_feed_line(line: &str) -> Result<Option<(String, PollOverrides)>, InvalidOverride> {
+        if line.trim_start().starts_with("exec:") {
+            return Ok(Some((String::from(line.trim()), PollOverrides::default())));
+        }
+        let line = line.trim();
+        let (url, rest) = line.split_at(line.find(char::is_whitespace).unwrap_or(line.len()));
+        if url.is_empty() || url.starts_with('#') {
+            return Ok(None);
+        }
+        let mut overrides = PollOverrides::default();
+        for pair in split_override_pairs(rest.trim_start()) {
+            overrides.apply(pair)?;
+        }
+        Ok(Some((String::from(url), overrides)))
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        *self == PollOverrides::default()
+    }
+}
+
+/// Split text into `key=value` pairs, each starting at a known key after
+/// whitespace, so values may contain whitespace themselves
+fn split_override_pairs(text: &str) -> Vec<&str> {
+    let is_pair_start = |idx: usize| {
+        (idx == 0 || text[..idx].ends_with(char::is_whitespace))
+            && OVERRIDE_KEYS
+                .iter()
+                .any(|key| text[idx..].starts_with(key) && text[idx + key.len()..].starts_with('='))
+    };
+    let mut starts: Vec<usize> = text
+        .char_indices()
+        .map(|(idx, _)| idx)
+        .filter(|idx| is_pair_start(*idx))
+        .collect();
+    if starts.first() != Some(&0) {
+        starts.insert(0, 0);
+    }
+    let ends = starts.iter().skip(1).copied().chain(Some(text.len()));
+    starts
+        .iter()
+        .zip(ends)
+        .map(|(start, end)| text[*start..end].trim())
+        .filter(|pair| !pair.is_empty())
+        .collect()
+}
+
+/// Overrides as `key=value` pairs for showing to people, so the values of
+/// sensitive headers are hidden
+impl fmt::Display for PollOverrides {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut pairs = Vec::new();
+        if let Some(request_timeout) = self.request_timeout {
+            pairs.push(format!("request_timeout={}", request_timeout.as_secs()));
+        }
+        if let Some(min_fetch_period) = self.min_fetch_period {
+            pairs.push(format!("min_fetch_period={}", min_fetch_period.as_secs()));
+        }
+        if let Some(max_fetch_period) = self.max_fetch_period {
+            pairs.push(format!("max_fetch_period={}", max_fetch_period.as_secs()));
+        }
+        if let Some(retain_src) = self.retain_src {
+            pairs.push(format!("retain_src={}", retain_src));
+        }
+        if let Some(skip_entry_update) = self.skip_entry_update {
+            pairs.push(format!("skip_entry_update={}", skip_entry_update));
+        }
+        if let Some(credentials) = &self.credentials {
+            pairs.push(format!("credentials={}", credentials));
+        }
+        for (name, value) in &self.headers {
+            if client::is_sensitive_header(name) {
+                pairs.push(format!("header={}:<redacted>", name));
+            } else if let Ok(value) = value.to_str() {
+                pairs.push(format!("header={}:{}", name, value));
+            }
+        }
+        write!(f, "{}", pairs.join(" "))
+    }
+}
+
+impl From<&models::Subscription> for PollOverrides {
+    fn from(subscription: &models::Subscription) -> Self {
+        let seconds = |secs: Option<i32>| {
+            secs.and_then(|secs| u64::try_from(secs).ok())
+                .map(Duration::from_secs)
+        };
+        PollOverrides {
+            request_timeout: seconds(subscription.request_timeout),
+            min_fetch_period: seconds(subscription.min_fetch_period),
+            max_fetch_period: seconds(subscription.max_fetch_period),
+            retain_src: subscription.retain_src,
+            skip_entry_update: subscription.skip_entry_update,
+            headers: subscription
+                .headers
+                .as_deref()
+                .unwrap_or("")
+                .lines()
+                .filter_map(client::parse_header)
+                .collect(),
+            credentials: subscription.credentials.clone(),
+        }
+    }
+}
@@ -1,6 +1,9 @@
 #![allow(clippy::module_name_repetitions)]
 
 use feed_rs::model::Feed;
+
+use super::discovery::FeedLink;
+use super::encoding;
 use std::error::Error;
 use std::fmt;
 use std::panic;
@@ -15,17 +18,79 @@ pub struct ConditionalGetData {
 pub struct FeedFetchResult {
     pub id: String,
     pub url: String,
+    /// URL of the response, after following redirects
+    pub final_url: String,
+    /// URL reached by following only permanent redirects, if any
+    pub moved_to: Option<String>,
     pub status: String,
     pub headers: reqwest::header::HeaderMap,
     pub body: String,
+    /// Name of the character encoding the body was decoded from
+    pub encoding: String,
+}
+
+impl FeedFetchResult {
+    #[must_use]
+    pub fn status_code(&self) -> Option<reqwest::StatusCode> {
+        reqwest::StatusCode::from_bytes(self.status.as_bytes()).ok()
+    }
+
+    /// Parse the body as a feed
+    ///
+    /// # Errors
+    ///
+    /// Will return `ParseFeedError` if the body isn't a feed
+    pub fn parse_feed(&self) -> Result<Feed, feed_rs::parser::ParseFeedError> {
+        feed_rs::parser::parse(encoding::utf8_document(&self.body).as_bytes())
+    }
+}
+
+/// How many entries of an updated feed were new, and how many already
+/// stored were updated
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EntryCounts {
+    pub new: usize,
+    pub updated: usize,
+}
+
+/// An entry that polling a feed would add, update or mark defunct
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntryPreview {
+    pub id: String,
+    pub guid: String,
+    pub title: Option<String>,
+}
+
+/// What polling a feed would change, worked out without changing anything.
+/// The result is `Fetched` or `NotModified`, never `Updated`.
+#[derive(Debug)]
+pub struct FeedPollPreview {
+    pub result: FeedPollResult,
+    pub new_entries: Vec<EntryPreview>,
+    pub updated_entries: Vec<EntryPreview>,
+    pub defunct_entries: Vec<EntryPreview>,
 }
 
 #[derive(Debug)]
 pub enum FeedPollResult {
     Skipped,
-    NotModified { fetch: FeedFetchResult },
-    Fetched { fetch: FeedFetchResult, feed: Feed },
-    Updated { fetch: FeedFetchResult, feed: Feed },
+    NotModified {
+        fetch: FeedFetchResult,
+    },
+    Fetched {
+        fetch: FeedFetchResult,
+        feed: Feed,
+    },
+    /// Fetched, but with the same body as the last successful fetch
+    Unchanged {
+        fetch: FeedFetchResult,
+        feed: Feed,
+    },
+    Updated {
+        fetch: FeedFetchResult,
+        feed: Feed,
+        entries: EntryCounts,
+    },
 }
 
 impl FeedPollResult {
@@ -33,13 +98,40 @@ impl FeedPollResult {
     ///
     /// Will panic if attempted on something other than `FeedPollResult::Fetched`
     #[must_use]
-    pub fn fetched_to_updated(self) -> FeedPollResult {
+    pub fn fetched_to_updated(self, entries: EntryCounts) -> FeedPollResult {
         match self {
-            Self::Fetched { fetch, feed } => Self::Updated { fetch, feed },
+            Self::Fetched { fetch, feed } => Self::Updated {
+                fetch,
+                feed,
+                entries,
+            },
             _ => panic!("expected FeedPollResult::Fetched, got {:?}", self),
         }
     }
 
+    /// # Panics
+    ///
+    /// Will panic if attempted on something other than `FeedPollResult::Fetched`
+    #[must_use]
+    pub fn fetched_to_unchanged(self) -> FeedPollResult {
+        match self {
+            Self::Fetched { fetch, feed } => Self::Unchanged { fetch, feed },
+            _ => panic!("expected FeedPollResult::Fetched, got {:?}", self),
+        }
+    }
+
+    /// The response behind this result, if a fetch was made
+    #[must_use]
+    pub fn fetch(&self) -> Option<&FeedFetchResult> {
+        match self {
+            Self::Skipped => None,
+            Self::NotModified { fetch }
+            | Self::Fetched { fetch, .. }
+            | Self::Unchanged { fetch, .. }
+            | Self::Updated { fetch, .. } => Some(fetch),
+        }
+    }
+
     /// # Panics
     ///
     /// Will panic if attempted on something other than `FeedPollResult::Fetched`
@@ -55,24 +147,69 @@ impl FeedPollResult {
 #[derive(Debug)]
 pub enum FeedPollError {
     FetchTimeError(time::OutOfRangeError),
+    CredentialsNotFound(String),
     Timedout(reqwest::Error),
     NotFound(reqwest::Error),
     FetchError(reqwest::Error),
+    ReadError(std::io::Error),
+    CommandFailed {
+        status: String,
+        stderr: String,
+    },
     DatabaseError(diesel::result::Error),
     FetchFailed {
         fetch: FeedFetchResult,
     },
+    Gone {
+        fetch: FeedFetchResult,
+    },
     ParseError {
         fetch: FeedFetchResult,
         error: feed_rs::parser::ParseFeedError,
     },
+    FeedsDiscovered {
+        fetch: FeedFetchResult,
+        feed_links: Vec<FeedLink>,
+    },
     UpdateError {
         fetch: FeedFetchResult,
         feed: Feed,
         error: diesel::result::Error,
     },
 }
-impl FeedPollError {}
+impl FeedPollError {
+    /// A short name for the kind of failure, stable enough for monitoring to match on
+    #[must_use]
+    pub fn category(&self) -> &'static str {
+        match self {
+            Self::FetchTimeError(_) => "schedule",
+            Self::CredentialsNotFound(_) => "credentials",
+            Self::Timedout(_) => "timeout",
+            Self::NotFound(_) => "not_found",
+            Self::FetchError(_) => "fetch",
+            Self::ReadError(_) => "read",
+            Self::CommandFailed { .. } => "command",
+            Self::DatabaseError(_) | Self::UpdateError { .. } => "database",
+            Self::FetchFailed { .. } => "http_status",
+            Self::Gone { .. } => "gone",
+            Self::ParseError { .. } => "parse",
+            Self::FeedsDiscovered { .. } => "not_a_feed",
+        }
+    }
+
+    /// The response that led to this error, if one was received
+    #[must_use]
+    pub fn fetch(&self) -> Option<&FeedFetchResult> {
+        match self {
+            Self::FetchFailed { fetch }
+            | Self::Gone { fetch }
+            | Self::ParseError { fetch, .. }
+            | Self::FeedsDiscovered { fetch, .. }
+            | Self::UpdateError { fetch, .. } => Some(fetch),
+            _ => None,
+        }
+    }
+}
 impl fmt::Display for FeedPollError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{:?}", &self)
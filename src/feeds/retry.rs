@@ -0,0 +1,78 @@
+#![allow(clippy::module_name_repetitions)]
+
+use rand::Rng;
+use std::convert::TryFrom;
+use std::time::Duration;
+
+use super::result::FeedPollError;
+
+/// Longest backoff before a retry, however many attempts have failed
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+/// How a poll retries a fetch that failed for what looks like a passing
+/// reason - a timeout, a connection error or a 5xx response
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Total attempts to fetch a feed within a poll, 1 to never retry
+    pub max_attempts: usize,
+    /// Delay before the first retry, doubled before each retry after that
+    pub base_delay: Duration,
+    /// Up to this much random delay is added before each retry, so that
+    /// feeds failing together don't all retry at the same moment
+    pub jitter: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            base_delay: Duration::from_secs(1),
+            jitter: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether a failed attempt, counting from 1, should be tried again
+    #[must_use]
+    pub fn should_retry(&self, attempt: usize, error: &FeedPollError) -> bool {
+        attempt < self.max_attempts && is_transient(error)
+    }
+
+    /// How long to wait after a failed attempt, counting from 1, before the next
+    #[must_use]
+    pub fn delay(&self, attempt: usize) -> Duration {
+        let exponent = u32::try_from(attempt.saturating_sub(1)).unwrap_or(u32::MAX);
+        let backoff = self
+            .base_delay
+            .checked_mul(2_u32.saturating_pow(exponent))
+            .map_or(MAX_BACKOFF, |backoff| backoff.min(MAX_BACKOFF));
+        let jitter_millis = u64::try_from(self.jitter.as_millis()).unwrap_or(u64::MAX);
+        let jitter = if jitter_millis > 0 {
+            Duration::from_millis(rand::thread_rng().gen_range(0, jitter_millis.saturating_add(1)))
+        } else {
+            Duration::from_millis(0)
+        };
+        backoff + jitter
+    }
+}
+
+/// Whether an error might not happen again if the fetch were retried. A 5xx
+/// response with `Retry-After` isn't retried, since the server has said when
+/// to come back and that's left to the fetch schedule.
+#[must_use]
+pub fn is_transient(error: &FeedPollError) -> bool {
+    match error {
+        FeedPollError::Timedout(_) => true,
+        FeedPollError::FetchError(error) => {
+            error.is_timeout() || error.is_connect() || error.is_body()
+        }
+        FeedPollError::FetchFailed { fetch } => {
+            fetch
+                .status_code()
+                .map_or(false, |status| status.is_server_error())
+                && !fetch.headers.contains_key(reqwest::header::RETRY_AFTER)
+        }
+        _ => false,
+    }
+}
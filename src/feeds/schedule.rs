@@ -0,0 +1,132 @@
+use chrono::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use std::convert::TryFrom;
+use std::time::Duration;
+
+use super::result::FeedPollError;
+use crate::db::{find_last_fetch_time, find_last_not_before, find_recent_entry_published};
+use crate::{rsscloud, websub};
+
+/// Number of recent entries used to estimate how often a feed posts
+const CADENCE_SAMPLE_SIZE: i64 = 20;
+
+/// When a poll should fetch a feed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FetchSchedule {
+    /// Once the feed is due, going by `next_fetch_time`
+    WhenDue,
+    /// As soon as allowed by `earliest_fetch_time`, for a feed announced as updated
+    WhenAllowed,
+    /// Right away, whenever it was last fetched
+    Now,
+}
+
+/// Work out when a feed is next due to be fetched, based on when it was last
+/// fetched and how often it posts, but no earlier than any "not before" time
+/// recorded from the server's caching hints. Feeds with updates pushed by a
+/// WebSub hub or announced by an rssCloud server are only polled at the
+/// maximum period, as a fallback. Returns
+/// None if the feed was never fetched.
+///
+/// # Errors
+///
+/// Will return `FeedPollError` for any DB failure or out of range duration
+pub fn next_fetch_time(
+    conn: &SqliteConnection,
+    url: &str,
+    min_fetch_period: Duration,
+    max_fetch_period: Duration,
+) -> Result<Option<DateTime<Utc>>, FeedPollError> {
+    let last_fetch_time = match find_last_fetch_time(&conn, &url)
+        .and_then(|last_fetch_time| DateTime::parse_from_rfc3339(&last_fetch_time).ok())
+    {
+        Some(last_fetch_time) => last_fetch_time.with_timezone(&Utc),
+        None => return Ok(None),
+    };
+    let published: Vec<DateTime<Utc>> =
+        find_recent_entry_published(&conn, &url, CADENCE_SAMPLE_SIZE)
+            .map_err(FeedPollError::DatabaseError)?
+            .iter()
+            .filter_map(|published| DateTime::parse_from_rfc3339(published).ok())
+            .map(|published| published.with_timezone(&Utc))
+            .collect();
+    let min_fetch_period =
+        if websub::is_push_active(&conn, &url) || rsscloud::is_push_active(&conn, &url) {
+            max_fetch_period
+        } else {
+            min_fetch_period
+        };
+    let interval = fetch_interval(
+        &published,
+        last_fetch_time,
+        min_fetch_period,
+        max_fetch_period,
+    );
+    let interval = chrono::Duration::from_std(interval).map_err(FeedPollError::FetchTimeError)?;
+    Ok(Some(not_before_or(conn, url, last_fetch_time + interval)))
+}
+
+/// Work out the earliest a feed may be fetched again, at the minimum period
+/// after it was last fetched but no earlier than any "not before" time
+/// recorded from the server's caching hints. This keeps feeds announced as
+/// updated from being fetched more often than any other feed. Returns None
+/// if the feed was never fetched.
+///
+/// # Errors
+///
+/// Will return `FeedPollError` for an out of range duration
+pub fn earliest_fetch_time(
+    conn: &SqliteConnection,
+    url: &str,
+    min_fetch_period: Duration,
+) -> Result<Option<DateTime<Utc>>, FeedPollError> {
+    let last_fetch_time = match find_last_fetch_time(&conn, &url)
+        .and_then(|last_fetch_time| DateTime::parse_from_rfc3339(&last_fetch_time).ok())
+    {
+        Some(last_fetch_time) => last_fetch_time.with_timezone(&Utc),
+        None => return Ok(None),
+    };
+    let interval =
+        chrono::Duration::from_std(min_fetch_period).map_err(FeedPollError::FetchTimeError)?;
+    Ok(Some(not_before_or(conn, url, last_fetch_time + interval)))
+}
+
+/// The later of a fetch time and any recorded "not before" time
+fn not_before_or(conn: &SqliteConnection, url: &str, fetch_time: DateTime<Utc>) -> DateTime<Utc> {
+    let not_before = find_last_not_before(&conn, &url)
+        .and_then(|not_before| DateTime::parse_from_rfc3339(&not_before).ok())
+        .map(|not_before| not_before.with_timezone(&Utc));
+    match not_before {
+        Some(not_before) if not_before > fetch_time => not_before,
+        _ => fetch_time,
+    }
+}
+
+/// Pick a polling interval of half the average time between posts, so a
+/// new entry is usually picked up within half a posting cycle. A feed that
+/// has gone quiet for longer than usual backs off further. Feeds without
+/// publish dates get the minimum period, and the result is always kept
+/// between the minimum and maximum periods.
+#[must_use]
+pub fn fetch_interval(
+    published: &[DateTime<Utc>],
+    as_of: DateTime<Utc>,
+    min_fetch_period: Duration,
+    max_fetch_period: Duration,
+) -> Duration {
+    let max_fetch_period = max_fetch_period.max(min_fetch_period);
+    let (newest, oldest) = match (published.iter().max(), published.iter().min()) {
+        (Some(newest), Some(oldest)) => (*newest, *oldest),
+        _ => return min_fetch_period,
+    };
+    let gaps = i32::try_from(published.len() - 1)
+        .unwrap_or(i32::MAX)
+        .max(1);
+    let average_gap = (newest - oldest) / gaps;
+    let quiet_time = as_of - newest;
+    (average_gap.max(quiet_time) / 2)
+        .to_std()
+        .map_or(min_fetch_period, |interval| {
+            interval.clamp(min_fetch_period, max_fetch_period)
+        })
+}
@@ -1,6 +1,7 @@
 use super::Context;
 use crate::db::paginate_dsl::{PaginateDsl, Pagination};
 use crate::models;
+use crate::opml;
 use chrono::prelude::*;
 use diesel::prelude::*;
 use juniper::{graphql_object, FieldResult};
@@ -46,6 +47,14 @@ impl RootQuery {
         query = query.paginate(pagination).order(published.desc());
         Ok(query.load::<models::Entry>(&conn)?)
     }
+
+    fn opml(context: &Context, title: Option<String>) -> FieldResult<String> {
+        let conn = context.pool.get()?;
+        Ok(opml::export(
+            &conn,
+            title.as_deref().unwrap_or(opml::DEFAULT_TITLE),
+        )?)
+    }
 }
 
 #[graphql_object(
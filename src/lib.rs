@@ -10,4 +10,8 @@ pub mod db;
 pub mod feeds;
 pub mod gql;
 pub mod models;
+pub mod opml;
+pub mod rsscloud;
 pub mod schema;
+pub mod sources;
+pub mod websub;
@@ -6,7 +6,22 @@ mod cli;
 
 use std::error::Error;
 
+use cli::subcommands::fetch::TooManyFailures;
+
 #[tokio::main]
-pub async fn main() -> Result<(), Box<dyn Error>> {
+async fn run() -> Result<(), Box<dyn Error>> {
     cli::execute().await
 }
+
+/// Run the CLI, exiting only once the runtime has shut down, with a status
+/// of 2 for a fetch with too many failures or 1 for any other error
+pub fn main() {
+    if let Err(error) = run() {
+        if let Some(error) = error.downcast_ref::<TooManyFailures>() {
+            log::error!("{}", error);
+            std::process::exit(TooManyFailures::EXIT_CODE);
+        }
+        eprintln!("Error: {:?}", error);
+        std::process::exit(1);
+    }
+}
@@ -1,4 +1,7 @@
-use super::schema::{entries, feed_history, feeds};
+use super::schema::{
+    entries, feed_history, feeds, rsscloud_registrations, subscription_sources, subscriptions,
+    websub_subscriptions,
+};
 use juniper::GraphQLObject;
 use serde::{Deserialize, Serialize};
 
@@ -24,6 +27,7 @@ pub struct Entry {
 pub struct EntryUpsert<'a> {
     pub skip_update: bool,
     pub id: &'a str,
+    pub guid: &'a str,
     pub feed_id: &'a str,
     pub json: &'a str,
     pub title: &'a str,
@@ -35,10 +39,19 @@ pub struct EntryUpsert<'a> {
     pub now: &'a str,
 }
 
+/// What upserting an entry did to the stored entry
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EntryUpsertResult {
+    New,
+    Updated,
+    Unchanged,
+}
+
 #[derive(Insertable)]
 #[table_name = "entries"]
 pub struct EntryNew<'a> {
     pub id: &'a str,
+    pub guid: &'a str,
     pub feed_id: &'a str,
     pub published: &'a str,
     pub updated: &'a str,
@@ -55,6 +68,7 @@ pub struct EntryNew<'a> {
 #[derive(AsChangeset)]
 #[table_name = "entries"]
 pub struct EntryUpdate<'a> {
+    pub guid: Option<&'a str>,
     pub published: Option<&'a str>,
     pub updated: Option<&'a str>,
     pub modified_at: Option<&'a str>,
@@ -80,6 +94,10 @@ pub struct FeedHistory {
     pub json: Option<String>,
     pub is_error: Option<bool>,
     pub error_text: Option<String>,
+    pub not_before: Option<String>,
+    pub encoding: Option<String>,
+    pub attempt: Option<i32>,
+    pub body_hash: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -92,6 +110,9 @@ pub struct FeedHistoryNewSuccess<'a> {
     pub status: &'a str,
     pub etag: &'a str,
     pub last_modified: &'a str,
+    pub not_before: Option<&'a str>,
+    pub encoding: &'a str,
+    pub body_hash: &'a str,
 }
 
 #[derive(Insertable)]
@@ -102,6 +123,8 @@ pub struct FeedHistoryNewError<'a> {
     pub created_at: &'a str,
     pub is_error: bool,
     pub error_text: &'a str,
+    pub not_before: Option<&'a str>,
+    pub attempt: i32,
 }
 
 #[derive(Queryable, PartialEq, Debug, Serialize, Deserialize)]
@@ -157,3 +180,148 @@ pub struct FeedUpdate<'a> {
     pub json: Option<&'a str>,
     pub last_entry_published: Option<&'a str>,
 }
+
+#[derive(Queryable, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Subscription {
+    pub id: Option<String>,
+    pub url: Option<String>,
+    pub created_at: Option<String>,
+    pub modified_at: Option<String>,
+    pub title: Option<String>,
+    pub category: Option<String>,
+    pub source_id: Option<String>,
+    pub active: Option<bool>,
+    pub request_timeout: Option<i32>,
+    pub min_fetch_period: Option<i32>,
+    pub retain_src: Option<bool>,
+    pub skip_entry_update: Option<bool>,
+    pub paused_at: Option<String>,
+    pub paused_reason: Option<String>,
+    pub resumed_at: Option<String>,
+    pub max_fetch_period: Option<i32>,
+    pub headers: Option<String>,
+    pub credentials: Option<String>,
+    pub inactive_reason: Option<String>,
+}
+
+pub struct SubscriptionUpsert<'a> {
+    pub url: &'a str,
+    pub title: Option<&'a str>,
+    pub category: Option<&'a str>,
+    pub source_id: Option<&'a str>,
+    pub active: Option<bool>,
+    pub now: &'a str,
+}
+
+#[derive(Insertable)]
+#[table_name = "subscriptions"]
+pub struct SubscriptionNew<'a> {
+    pub id: &'a str,
+    pub url: &'a str,
+    pub created_at: &'a str,
+    pub modified_at: &'a str,
+    pub title: Option<&'a str>,
+    pub category: Option<&'a str>,
+    pub source_id: Option<&'a str>,
+    pub active: bool,
+}
+
+#[derive(AsChangeset)]
+#[table_name = "subscriptions"]
+pub struct SubscriptionUpdate<'a> {
+    pub modified_at: Option<&'a str>,
+    pub title: Option<&'a str>,
+    pub category: Option<&'a str>,
+    pub active: Option<bool>,
+}
+
+#[derive(AsChangeset)]
+#[table_name = "subscriptions"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct SubscriptionOverridesUpdate<'a> {
+    pub modified_at: &'a str,
+    pub request_timeout: Option<i32>,
+    pub min_fetch_period: Option<i32>,
+    pub max_fetch_period: Option<i32>,
+    pub retain_src: Option<bool>,
+    pub skip_entry_update: Option<bool>,
+    pub headers: Option<&'a str>,
+    pub credentials: Option<&'a str>,
+}
+
+#[derive(Queryable, PartialEq, Debug, Serialize, Deserialize)]
+pub struct SubscriptionSource {
+    pub id: Option<String>,
+    pub url: Option<String>,
+    pub created_at: Option<String>,
+    pub modified_at: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name = "subscription_sources"]
+pub struct SubscriptionSourceNew<'a> {
+    pub id: &'a str,
+    pub url: &'a str,
+    pub created_at: &'a str,
+    pub modified_at: &'a str,
+}
+
+#[derive(Queryable, PartialEq, Debug, Serialize, Deserialize)]
+pub struct WebSubSubscription {
+    pub id: Option<String>,
+    pub feed_url: Option<String>,
+    pub hub_url: Option<String>,
+    pub topic_url: Option<String>,
+    pub secret: Option<String>,
+    pub state: Option<String>,
+    pub lease_expires_at: Option<String>,
+    pub created_at: Option<String>,
+    pub modified_at: Option<String>,
+    pub callback_token: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name = "websub_subscriptions"]
+pub struct WebSubSubscriptionNew<'a> {
+    pub id: &'a str,
+    pub feed_url: &'a str,
+    pub hub_url: &'a str,
+    pub topic_url: &'a str,
+    pub secret: &'a str,
+    pub state: &'a str,
+    pub created_at: &'a str,
+    pub modified_at: &'a str,
+    pub callback_token: &'a str,
+}
+
+#[derive(AsChangeset)]
+#[table_name = "websub_subscriptions"]
+pub struct WebSubSubscriptionRequest<'a> {
+    pub feed_url: &'a str,
+    pub hub_url: &'a str,
+    pub topic_url: &'a str,
+    pub secret: &'a str,
+    pub state: &'a str,
+    pub modified_at: &'a str,
+    pub callback_token: &'a str,
+}
+
+#[derive(Queryable, PartialEq, Debug, Serialize, Deserialize)]
+pub struct RssCloudRegistration {
+    pub id: Option<String>,
+    pub feed_url: Option<String>,
+    pub cloud_url: Option<String>,
+    pub registered_at: Option<String>,
+    pub created_at: Option<String>,
+    pub modified_at: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name = "rsscloud_registrations"]
+pub struct RssCloudRegistrationNew<'a> {
+    pub id: &'a str,
+    pub feed_url: &'a str,
+    pub cloud_url: &'a str,
+    pub created_at: &'a str,
+    pub modified_at: &'a str,
+}
@@ -0,0 +1,238 @@
+use chrono::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::io::{Read, Write};
+use xml::reader::{EventReader, XmlEvent};
+use xml::writer::{self, EmitterConfig, EventWriter};
+
+use crate::db::{find_active_subscriptions, find_feeds_with_subscriptions};
+use crate::feeds::local;
+
+pub const DEFAULT_TITLE: &str = "feedspool subscriptions";
+
+/// A feed outline found in an OPML document
+#[derive(Debug, PartialEq)]
+pub struct Outline {
+    pub xml_url: String,
+    pub html_url: Option<String>,
+    pub title: Option<String>,
+    pub category: Option<String>,
+}
+
+/// Parse the feed outlines from an OPML 1.0 or 2.0 document. Outlines nested
+/// in category outlines get a category built from the enclosing titles, e.g.
+/// "Tech/Rust". Otherwise, the first path in an OPML 2.0 `category` attribute
+/// is used.
+///
+/// # Errors
+///
+/// Returns `xml::reader::Error` for malformed XML
+pub fn parse<R: Read>(source: R) -> Result<Vec<Outline>, xml::reader::Error> {
+    let mut outlines = Vec::new();
+    // Titles of enclosing outlines, with None for outlines that are feeds
+    let mut parents: Vec<Option<String>> = Vec::new();
+
+    for event in EventReader::new(source) {
+        match event? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } if is_outline(&name) => {
+                let attr = |key: &str| {
+                    attributes
+                        .iter()
+                        .find(|attr| attr.name.local_name.eq_ignore_ascii_case(key))
+                        .map(|attr| String::from(attr.value.trim()))
+                        .filter(|value| !value.is_empty())
+                };
+                let title = attr("title").or_else(|| attr("text"));
+                if let Some(xml_url) = attr("xmlUrl") {
+                    outlines.push(Outline {
+                        xml_url,
+                        html_url: attr("htmlUrl"),
+                        title,
+                        category: category_path(&parents).or_else(|| {
+                            attr("category").and_then(|category| {
+                                category
+                                    .split(',')
+                                    .map(|path| path.trim().trim_matches('/'))
+                                    .find(|path| !path.is_empty())
+                                    .map(String::from)
+                            })
+                        }),
+                    });
+                    parents.push(None);
+                } else {
+                    parents.push(title);
+                }
+            }
+            XmlEvent::EndElement { name } if is_outline(&name) => {
+                parents.pop();
+            }
+            _ => (),
+        }
+    }
+
+    Ok(outlines)
+}
+
+fn is_outline(name: &xml::name::OwnedName) -> bool {
+    name.local_name.eq_ignore_ascii_case("outline")
+}
+
+fn category_path(parents: &[Option<String>]) -> Option<String> {
+    let path: Vec<&str> = parents.iter().filter_map(Option::as_deref).collect();
+    if path.is_empty() {
+        None
+    } else {
+        Some(path.join("/"))
+    }
+}
+
+/// Collect outlines for active subscriptions, using details of their feeds
+/// where they've been fetched. Categories come from the subscriptions. Local
+/// files and commands are left out, since exports are shared with other tools
+/// and served over the web.
+///
+/// # Errors
+///
+/// Returns `diesel::result::Error` for any DB failure
+pub fn find_outlines(conn: &SqliteConnection) -> Result<Vec<Outline>, diesel::result::Error> {
+    let non_blank = |value: Option<String>| value.filter(|value| !value.is_empty());
+
+    let subscriptions: Vec<_> = find_active_subscriptions(conn)?
+        .into_iter()
+        .filter(|subscription| {
+            subscription
+                .url
+                .as_deref()
+                .map_or(false, |url| !local::is_local_source(url))
+        })
+        .collect();
+    let subscribed_ids: HashSet<&String> = subscriptions
+        .iter()
+        .filter_map(|subscription| subscription.id.as_ref())
+        .collect();
+
+    let mut seen_ids = HashSet::new();
+    let mut outlines = Vec::new();
+    for (feed, subscription) in find_feeds_with_subscriptions(conn)? {
+        if !feed
+            .id
+            .as_ref()
+            .map_or(false, |id| subscribed_ids.contains(id))
+        {
+            continue;
+        }
+        if let Some(xml_url) = non_blank(feed.url) {
+            let (title, category) = subscription.map_or((None, None), |subscription| {
+                (subscription.title, subscription.category)
+            });
+            seen_ids.extend(feed.id);
+            outlines.push(Outline {
+                xml_url,
+                html_url: non_blank(feed.link),
+                title: non_blank(feed.title).or_else(|| non_blank(title)),
+                category: non_blank(category),
+            });
+        }
+    }
+    for subscription in subscriptions {
+        if let (Some(id), Some(xml_url)) = (subscription.id, subscription.url) {
+            if !seen_ids.contains(&id) {
+                outlines.push(Outline {
+                    xml_url,
+                    html_url: None,
+                    title: non_blank(subscription.title),
+                    category: non_blank(subscription.category),
+                });
+            }
+        }
+    }
+    Ok(outlines)
+}
+
+#[derive(Default)]
+struct CategoryNode<'a> {
+    children: BTreeMap<&'a str, CategoryNode<'a>>,
+    outlines: Vec<&'a Outline>,
+}
+
+/// Render an OPML 2.0 document for all known feeds as a string
+///
+/// # Errors
+///
+/// Returns Err for any DB failure or failure in writing XML
+pub fn export(conn: &SqliteConnection, title: &str) -> Result<String, Box<dyn Error>> {
+    let outlines = find_outlines(conn)?;
+    let mut output = Vec::new();
+    write(&mut output, title, &outlines)?;
+    Ok(String::from_utf8(output)?)
+}
+
+/// Write an OPML 2.0 document for the given outlines, nesting them in
+/// category outlines built from their "/"-separated category paths.
+///
+/// # Errors
+///
+/// Returns `xml::writer::Error` for any failure in writing XML
+pub fn write<W: Write>(sink: W, title: &str, outlines: &[Outline]) -> Result<(), writer::Error> {
+    let mut root = CategoryNode::default();
+    for outline in outlines {
+        let mut node = &mut root;
+        if let Some(category) = &outline.category {
+            for name in category.split('/').filter(|name| !name.is_empty()) {
+                node = node.children.entry(name).or_default();
+            }
+        }
+        node.outlines.push(outline);
+    }
+
+    let now = Utc::now().to_rfc2822();
+    let mut writer = EmitterConfig::new()
+        .perform_indent(true)
+        .create_writer(sink);
+    writer.write(writer::XmlEvent::start_element("opml").attr("version", "2.0"))?;
+    writer.write(writer::XmlEvent::start_element("head"))?;
+    writer.write(writer::XmlEvent::start_element("title"))?;
+    writer.write(writer::XmlEvent::characters(title))?;
+    writer.write(writer::XmlEvent::end_element())?;
+    writer.write(writer::XmlEvent::start_element("dateCreated"))?;
+    writer.write(writer::XmlEvent::characters(&now))?;
+    writer.write(writer::XmlEvent::end_element())?;
+    writer.write(writer::XmlEvent::end_element())?;
+    writer.write(writer::XmlEvent::start_element("body"))?;
+    write_category_node(&mut writer, &root)?;
+    writer.write(writer::XmlEvent::end_element())?;
+    writer.write(writer::XmlEvent::end_element())?;
+    Ok(())
+}
+
+fn write_category_node<W: Write>(
+    writer: &mut EventWriter<W>,
+    node: &CategoryNode,
+) -> Result<(), writer::Error> {
+    for (name, child) in &node.children {
+        writer.write(
+            writer::XmlEvent::start_element("outline")
+                .attr("text", name)
+                .attr("title", name),
+        )?;
+        write_category_node(writer, child)?;
+        writer.write(writer::XmlEvent::end_element())?;
+    }
+    for outline in &node.outlines {
+        let title = outline.title.as_deref().unwrap_or(&outline.xml_url);
+        let mut element = writer::XmlEvent::start_element("outline")
+            .attr("type", "rss")
+            .attr("text", title)
+            .attr("title", title)
+            .attr("xmlUrl", &outline.xml_url);
+        if let Some(html_url) = &outline.html_url {
+            element = element.attr("htmlUrl", html_url);
+        }
+        writer.write(element)?;
+        writer.write(writer::XmlEvent::end_element())?;
+    }
+    Ok(())
+}
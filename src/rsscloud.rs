@@ -0,0 +1,294 @@
+#![allow(clippy::module_name_repetitions)]
+
+use chrono::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::db::writer::DbWriter;
+use crate::db::{
+    feed_id_from_url, find_rsscloud_registration, update_rsscloud_registered,
+    upsert_rsscloud_request,
+};
+use crate::models;
+
+pub const NOTIFY_PATH: &str = "/rsscloud/";
+
+/// Clouds forget registrations that aren't renewed within this long
+const REGISTRATION_LIFETIME_HOURS: i64 = 25;
+/// Renew a registration once it's this old
+const REGISTRATION_RENEWAL_HOURS: i64 = 24;
+/// Ask a cloud again after a failed registration once this long has passed
+const FAILED_RETRY_HOURS: i64 = 1;
+
+/// Settings for registering with rssCloud servers advertised by polled feeds
+#[derive(Clone, Debug)]
+pub struct RssCloudOptions {
+    /// Public base URL of the `serve` web server, where clouds will send notifications
+    pub callback_base_url: String,
+}
+
+/// A cloud advertised by the `<cloud>` element of an RSS channel
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Cloud {
+    pub domain: String,
+    pub port: String,
+    pub path: String,
+    pub register_procedure: String,
+    pub protocol: String,
+}
+
+impl Cloud {
+    /// Find the `<cloud>` element in an RSS document, if any
+    #[must_use]
+    pub fn parse(body: &str) -> Option<Cloud> {
+        if !body.contains("<cloud") {
+            return None;
+        }
+        for event in EventReader::new(body.as_bytes()) {
+            match event {
+                Ok(XmlEvent::StartElement {
+                    name, attributes, ..
+                }) if name.local_name == "cloud" => {
+                    let attribute = |local_name: &str| {
+                        attributes
+                            .iter()
+                            .find(|attribute| attribute.name.local_name == local_name)
+                            .map(|attribute| attribute.value.trim().to_string())
+                            .unwrap_or_default()
+                    };
+                    return Some(Cloud {
+                        domain: attribute("domain"),
+                        port: attribute("port"),
+                        path: attribute("path"),
+                        register_procedure: attribute("registerProcedure"),
+                        protocol: attribute("protocol"),
+                    });
+                }
+                Err(_) => break,
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// URL to register with, or None if the cloud doesn't speak `http-post`,
+    /// the only protocol supported
+    #[must_use]
+    pub fn url(&self) -> Option<String> {
+        if !self.protocol.eq_ignore_ascii_case("http-post") || self.domain.is_empty() {
+            return None;
+        }
+        let port = if self.port.is_empty() {
+            "80"
+        } else {
+            &self.port
+        };
+        let scheme = if port == "443" { "https" } else { "http" };
+        let path = self.path.trim_start_matches('/');
+        url::Url::parse(&format!("{}://{}:{}/{}", scheme, self.domain, port, path))
+            .ok()
+            .map(String::from)
+    }
+}
+
+#[derive(Debug)]
+pub enum RssCloudError {
+    DatabaseError(diesel::result::Error),
+    RequestError(reqwest::Error),
+    CloudRejected { status: String, body: String },
+    InvalidCallbackUrl(String),
+}
+impl fmt::Display for RssCloudError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", &self)
+    }
+}
+impl Error for RssCloudError {}
+
+/// Ask a cloud to notify us of updates to a feed, unless already registered
+/// and not due for renewal. Without a cloud from a fresh fetch, the cloud
+/// from any earlier registration is used for renewal. Returns true if the
+/// cloud accepted a registration.
+///
+/// # Errors
+///
+/// Will return `RssCloudError` for any DB failure or if the cloud refuses the request
+pub async fn renew_registration(
+    db: &DbWriter,
+    feed_url: &str,
+    cloud: Option<Cloud>,
+    options: &RssCloudOptions,
+    client: &reqwest::Client,
+    request_timeout: Duration,
+) -> Result<bool, RssCloudError> {
+    let registration_id = feed_id_from_url(feed_url);
+    let existing = {
+        let registration_id = registration_id.clone();
+        db.run(move |conn| find_rsscloud_registration(conn, &registration_id))
+            .await
+            .map_err(RssCloudError::DatabaseError)?
+    };
+    let cloud_url = match (cloud.and_then(|cloud| cloud.url()), &existing) {
+        (Some(cloud_url), _) => cloud_url,
+        (
+            None,
+            Some(models::RssCloudRegistration {
+                cloud_url: Some(cloud_url),
+                ..
+            }),
+        ) => cloud_url.clone(),
+        _ => return Ok(false),
+    };
+    if let Some(existing) = &existing {
+        if !is_request_due(existing, &cloud_url, Utc::now()) {
+            return Ok(false);
+        }
+    }
+
+    let callback_url = url::Url::parse(&options.callback_base_url)
+        .map_err(|_| RssCloudError::InvalidCallbackUrl(options.callback_base_url.clone()))?;
+    let (domain, port) = match (
+        callback_url.host_str(),
+        callback_url.port_or_known_default(),
+    ) {
+        (Some(domain), Some(port)) => (domain, port.to_string()),
+        _ => {
+            return Err(RssCloudError::InvalidCallbackUrl(
+                options.callback_base_url.clone(),
+            ))
+        }
+    };
+    let path = format!(
+        "{}{}",
+        callback_url.path().trim_end_matches('/'),
+        NOTIFY_PATH
+    );
+    // Record the request first, since the cloud checks the callback before responding
+    {
+        let (feed_url, cloud_url) = (String::from(feed_url), cloud_url.clone());
+        db.run(move |conn| upsert_rsscloud_request(conn, &feed_url, &cloud_url))
+            .await
+            .map_err(RssCloudError::DatabaseError)?;
+    }
+
+    let response = client
+        .post(&cloud_url)
+        .timeout(request_timeout)
+        .form(&[
+            ("notifyProcedure", ""),
+            ("port", &port),
+            ("path", &path),
+            ("protocol", "http-post"),
+            ("domain", domain),
+            ("url1", feed_url),
+        ])
+        .send()
+        .await
+        .map_err(RssCloudError::RequestError)?;
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    if !status.is_success() || !is_notify_result_success(&body) {
+        return Err(RssCloudError::CloudRejected {
+            status: String::from(status.as_str()),
+            body,
+        });
+    }
+    db.run(move |conn| update_rsscloud_registered(conn, &registration_id))
+        .await
+        .map_err(RssCloudError::DatabaseError)?;
+    log::info!(
+        "Registered with rssCloud for {} at {}",
+        &feed_url,
+        &cloud_url
+    );
+    Ok(true)
+}
+
+/// Check the `<notifyResult success="...">` response to a registration
+fn is_notify_result_success(body: &str) -> bool {
+    for event in EventReader::new(body.as_bytes()) {
+        match event {
+            Ok(XmlEvent::StartElement {
+                name, attributes, ..
+            }) if name.local_name == "notifyResult" => {
+                return attributes
+                    .iter()
+                    .find(|attribute| attribute.name.local_name == "success")
+                    .map_or(true, |attribute| attribute.value.trim() != "false");
+            }
+            Err(_) => break,
+            _ => {}
+        }
+    }
+    true
+}
+
+fn is_request_due(
+    existing: &models::RssCloudRegistration,
+    cloud_url: &str,
+    now: DateTime<Utc>,
+) -> bool {
+    if existing.cloud_url.as_deref() != Some(cloud_url) {
+        return true;
+    }
+    let parse_time = |time: &Option<String>| {
+        time.as_ref()
+            .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+            .map(|time| time.with_timezone(&Utc))
+    };
+    parse_time(&existing.registered_at).map_or(true, |registered_at| {
+        now > registered_at + chrono::Duration::hours(REGISTRATION_RENEWAL_HOURS)
+    }) && parse_time(&existing.modified_at).map_or(true, |modified_at| {
+        now > modified_at + chrono::Duration::hours(FAILED_RETRY_HOURS)
+    })
+}
+
+/// Whether a cloud should currently be notifying us of updates to a feed
+#[must_use]
+pub fn is_push_active(conn: &SqliteConnection, feed_url: &str) -> bool {
+    match find_rsscloud_registration(conn, &feed_id_from_url(feed_url)) {
+        Ok(Some(models::RssCloudRegistration {
+            registered_at: Some(registered_at),
+            ..
+        })) => DateTime::parse_from_rfc3339(&registered_at).map_or(false, |registered_at| {
+            Utc::now() < registered_at + chrono::Duration::hours(REGISTRATION_LIFETIME_HOURS)
+        }),
+        _ => false,
+    }
+}
+
+/// Whether we asked a cloud to notify us of updates to a feed
+///
+/// # Errors
+///
+/// Will return `RssCloudError` for any DB failure
+pub fn is_registered(conn: &SqliteConnection, feed_url: &str) -> Result<bool, RssCloudError> {
+    Ok(
+        find_rsscloud_registration(conn, &feed_id_from_url(feed_url))
+            .map_err(RssCloudError::DatabaseError)?
+            .is_some(),
+    )
+}
+
+/// Handle a cloud checking the notification callback while registering.
+/// Returns the challenge to echo back if we asked to be notified of updates
+/// to the feed, or None if the request should be refused.
+///
+/// # Errors
+///
+/// Will return `RssCloudError` for any DB failure
+pub fn verify_challenge(
+    conn: &SqliteConnection,
+    params: &HashMap<String, String>,
+) -> Result<Option<String>, RssCloudError> {
+    match (params.get("url"), params.get("challenge")) {
+        (Some(feed_url), Some(challenge)) if is_registered(conn, feed_url)? => {
+            Ok(Some(challenge.clone()))
+        }
+        _ => Ok(None),
+    }
+}
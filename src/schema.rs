@@ -29,6 +29,10 @@ table! {
         json -> Nullable<Text>,
         is_error -> Nullable<Bool>,
         error_text -> Nullable<Text>,
+        not_before -> Nullable<Text>,
+        encoding -> Nullable<Text>,
+        attempt -> Nullable<Integer>,
+        body_hash -> Nullable<Text>,
     }
 }
 
@@ -48,4 +52,71 @@ table! {
     }
 }
 
-allow_tables_to_appear_in_same_query!(entries, feed_history, feeds,);
+table! {
+    subscriptions (id) {
+        id -> Nullable<Text>,
+        url -> Nullable<Text>,
+        created_at -> Nullable<Text>,
+        modified_at -> Nullable<Text>,
+        title -> Nullable<Text>,
+        category -> Nullable<Text>,
+        source_id -> Nullable<Text>,
+        active -> Nullable<Bool>,
+        request_timeout -> Nullable<Integer>,
+        min_fetch_period -> Nullable<Integer>,
+        retain_src -> Nullable<Bool>,
+        skip_entry_update -> Nullable<Bool>,
+        paused_at -> Nullable<Text>,
+        paused_reason -> Nullable<Text>,
+        resumed_at -> Nullable<Text>,
+        max_fetch_period -> Nullable<Integer>,
+        headers -> Nullable<Text>,
+        credentials -> Nullable<Text>,
+        inactive_reason -> Nullable<Text>,
+    }
+}
+
+table! {
+    subscription_sources (id) {
+        id -> Nullable<Text>,
+        url -> Nullable<Text>,
+        created_at -> Nullable<Text>,
+        modified_at -> Nullable<Text>,
+    }
+}
+
+table! {
+    rsscloud_registrations (id) {
+        id -> Nullable<Text>,
+        feed_url -> Nullable<Text>,
+        cloud_url -> Nullable<Text>,
+        registered_at -> Nullable<Text>,
+        created_at -> Nullable<Text>,
+        modified_at -> Nullable<Text>,
+    }
+}
+
+table! {
+    websub_subscriptions (id) {
+        id -> Nullable<Text>,
+        feed_url -> Nullable<Text>,
+        hub_url -> Nullable<Text>,
+        topic_url -> Nullable<Text>,
+        secret -> Nullable<Text>,
+        state -> Nullable<Text>,
+        lease_expires_at -> Nullable<Text>,
+        created_at -> Nullable<Text>,
+        modified_at -> Nullable<Text>,
+        callback_token -> Nullable<Text>,
+    }
+}
+
+allow_tables_to_appear_in_same_query!(
+    entries,
+    feed_history,
+    feeds,
+    rsscloud_registrations,
+    subscription_sources,
+    subscriptions,
+    websub_subscriptions,
+);
@@ -0,0 +1,188 @@
+#![allow(clippy::module_name_repetitions)]
+#![allow(clippy::large_enum_variant)]
+
+use chrono::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+use crate::db::writer::DbWriter;
+use crate::db::{
+    deactivate_unseen_source_subscriptions, feed_id_from_url, find_last_get_conditions,
+    insert_feed_history, insert_feed_history_error, upsert_subscription,
+};
+use crate::feeds::client::RequestOptions;
+use crate::feeds::fetch_url;
+use crate::feeds::result::{FeedFetchResult, FeedPollError};
+use crate::models;
+use crate::opml;
+
+#[derive(Debug)]
+pub enum SourceSyncResult {
+    NotModified {
+        fetch: FeedFetchResult,
+    },
+    Synced {
+        fetch: FeedFetchResult,
+        total: usize,
+        added: usize,
+        deactivated: usize,
+    },
+}
+
+#[derive(Debug)]
+pub enum SourceSyncError {
+    PollError(FeedPollError),
+    DatabaseError(diesel::result::Error),
+    ParseError {
+        fetch: FeedFetchResult,
+        error: xml::reader::Error,
+    },
+}
+impl fmt::Display for SourceSyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", &self)
+    }
+}
+impl Error for SourceSyncError {}
+
+/// Fetch an OPML subscription source and reconcile subscriptions with it:
+/// feeds new to the source are subscribed, feeds that disappeared from the
+/// source are marked inactive. Fetches are recorded in feed history, so the
+/// source gets the same conditional GET handling as feeds.
+///
+/// # Errors
+///
+/// Will return Err for any failure while syncing the source
+pub async fn sync_one_source(
+    db: &DbWriter,
+    client: &reqwest::Client,
+    url: &str,
+    request_timeout: Duration,
+) -> Result<SourceSyncResult, SourceSyncError> {
+    let source_url = String::from(url);
+    match fetch_and_reconcile(db, client, url, request_timeout).await {
+        Ok(sync_result) => {
+            db.run(move |conn| {
+                match &sync_result {
+                    SourceSyncResult::NotModified { fetch }
+                    | SourceSyncResult::Synced { fetch, .. } => {
+                        insert_feed_history(conn, fetch, false, None)
+                            .map_err(SourceSyncError::PollError)?;
+                    }
+                }
+                Ok(sync_result)
+            })
+            .await
+        }
+        Err(error) => {
+            db.run(move |conn| {
+                insert_feed_history_error(conn, &source_url, &error, None, 1)
+                    .map_err(SourceSyncError::PollError)?;
+                Err(error)
+            })
+            .await
+        }
+    }
+}
+
+async fn fetch_and_reconcile(
+    db: &DbWriter,
+    client: &reqwest::Client,
+    url: &str,
+    request_timeout: Duration,
+) -> Result<SourceSyncResult, SourceSyncError> {
+    let source_url = String::from(url);
+    let last_get_conditions = db
+        .run(move |conn| find_last_get_conditions(conn, &source_url))
+        .await;
+    let fetch = fetch_url(
+        client,
+        url,
+        request_timeout,
+        last_get_conditions,
+        &RequestOptions::default(),
+    )
+    .await
+    .map_err(SourceSyncError::PollError)?;
+    match fetch.status_code() {
+        Some(reqwest::StatusCode::OK) => {}
+        Some(reqwest::StatusCode::NOT_MODIFIED) => {
+            return Ok(SourceSyncResult::NotModified { fetch })
+        }
+        _ => {
+            return Err(SourceSyncError::PollError(FeedPollError::FetchFailed {
+                fetch,
+            }))
+        }
+    }
+
+    let outlines = match opml::parse(fetch.body.as_bytes()) {
+        Ok(outlines) => outlines,
+        Err(error) => return Err(SourceSyncError::ParseError { fetch, error }),
+    };
+
+    let source_url = String::from(url);
+    let (total, added, deactivated) = db
+        .run(move |conn| reconcile(conn, &source_url, &outlines))
+        .await?;
+
+    Ok(SourceSyncResult::Synced {
+        fetch,
+        total,
+        added,
+        deactivated,
+    })
+}
+
+/// Subscribe to the feeds listed by a source and deactivate those it no
+/// longer lists, returning the total listed, how many were added and how
+/// many were deactivated
+fn reconcile(
+    conn: &SqliteConnection,
+    url: &str,
+    outlines: &[opml::Outline],
+) -> Result<(usize, usize, usize), SourceSyncError> {
+    let now = Utc::now().to_rfc3339();
+    let source_id = feed_id_from_url(url);
+    let mut seen_subscription_ids = HashSet::new();
+    let mut added = 0;
+    for outline in outlines {
+        if !is_web_url(&outline.xml_url) {
+            log::warn!(
+                "Skipped {} from source {} - only http and https feeds can come from a source",
+                &outline.xml_url,
+                url
+            );
+            continue;
+        }
+        let upsert = models::SubscriptionUpsert {
+            url: &outline.xml_url,
+            title: outline.title.as_deref(),
+            category: outline.category.as_deref(),
+            source_id: Some(&source_id),
+            active: Some(true),
+            now: &now,
+        };
+        if upsert_subscription(conn, &upsert).map_err(SourceSyncError::DatabaseError)? {
+            added += 1;
+        }
+        seen_subscription_ids.insert(feed_id_from_url(&outline.xml_url));
+    }
+
+    let total = seen_subscription_ids.len();
+    let deactivated =
+        deactivate_unseen_source_subscriptions(conn, &source_id, seen_subscription_ids, &now)
+            .map_err(SourceSyncError::DatabaseError)?;
+    Ok((total, added, deactivated))
+}
+
+/// Whether a URL is on the web, rather than a local file or command that
+/// mustn't be accepted from a remote source
+fn is_web_url(url: &str) -> bool {
+    url::Url::parse(url).map_or(false, |url| {
+        url.scheme() == "http" || url.scheme() == "https"
+    })
+}
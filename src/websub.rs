@@ -0,0 +1,487 @@
+#![allow(clippy::module_name_repetitions)]
+#![allow(clippy::large_enum_variant)]
+
+use chrono::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use feed_rs::model::Feed;
+use hmac::{Hmac, Mac, NewMac};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use sha1::Sha1;
+use sha2::{Sha256, Sha384, Sha512};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+use crate::db::writer::DbWriter;
+use crate::db::{
+    feed_id_from_url, find_subscription, find_websub_subscription,
+    find_websub_subscription_by_callback, insert_feed_history, update_websub_state,
+    upsert_websub_request,
+};
+use crate::feeds::encoding::decode_body;
+use crate::feeds::options::{PollOptions, PollOverrides};
+use crate::feeds::result::{FeedFetchResult, FeedPollError, FeedPollResult};
+use crate::feeds::update_feed;
+use crate::models;
+
+pub const CALLBACK_PATH: &str = "/websub/";
+
+pub const STATE_PENDING: &str = "pending";
+pub const STATE_VERIFIED: &str = "verified";
+pub const STATE_DENIED: &str = "denied";
+
+/// Renew a subscription this long before its lease runs out
+const LEASE_RENEWAL_MARGIN_HOURS: i64 = 24;
+/// Ask a hub again if it hasn't verified a request after this long
+const PENDING_RETRY_HOURS: i64 = 1;
+/// Longest lease accepted from a hub, whatever it claims
+const MAX_LEASE_SECONDS: i64 = 60 * 60 * 24 * 365;
+
+/// Settings for subscribing to WebSub hubs advertised by polled feeds
+#[derive(Clone, Debug)]
+pub struct WebSubOptions {
+    /// Public base URL of the `serve` web server, where hubs will find callbacks
+    pub callback_base_url: String,
+    /// Lease to request from hubs in seconds, 0 to leave it up to the hub
+    pub lease_seconds: u64,
+}
+
+/// Hub and topic advertised by a feed for WebSub subscriptions
+#[derive(Clone, Debug, PartialEq)]
+pub struct HubLinks {
+    pub hub_url: String,
+    pub topic_url: String,
+}
+
+#[derive(Debug)]
+pub enum WebSubError {
+    DatabaseError(diesel::result::Error),
+    RequestError(reqwest::Error),
+    HubRejected { status: String, body: String },
+    UnknownSubscription,
+    PollError(FeedPollError),
+}
+impl fmt::Display for WebSubError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", &self)
+    }
+}
+impl Error for WebSubError {}
+
+/// Find the hub and topic for a fetched feed, from `rel="hub"` and
+/// `rel="self"` links in HTTP `Link` headers or else in the feed itself.
+/// Without a self link, the URL that was fetched is used as the topic.
+#[must_use]
+pub fn find_hub_links(fetch: &FeedFetchResult, feed: &Feed) -> Option<HubLinks> {
+    let base_url = url::Url::parse(&fetch.final_url).ok();
+    let resolve = |href: &str| match &base_url {
+        Some(base_url) => base_url.join(href).ok().map(String::from),
+        None => url::Url::parse(href).ok().map(String::from),
+    };
+
+    let mut links: Vec<(String, String)> = Vec::new();
+    for value in fetch.headers.get_all(reqwest::header::LINK).iter() {
+        if let Ok(value) = value.to_str() {
+            links.extend(parse_link_header(value));
+        }
+    }
+    for link in &feed.links {
+        if let Some(rel) = &link.rel {
+            links.push((link.href.clone(), rel.clone()));
+        }
+    }
+
+    let find_rel = |wanted: &str| {
+        links
+            .iter()
+            .filter(|(_, rel)| {
+                rel.split_whitespace()
+                    .any(|rel| rel.eq_ignore_ascii_case(wanted))
+            })
+            .find_map(|(href, _)| resolve(href))
+    };
+    Some(HubLinks {
+        hub_url: find_rel("hub")?,
+        topic_url: find_rel("self").unwrap_or_else(|| fetch.url.clone()),
+    })
+}
+
+/// Parse `<url>; rel="value"` pairs from a `Link` header
+fn parse_link_header(value: &str) -> Vec<(String, String)> {
+    value
+        .split(',')
+        .filter_map(|link| {
+            let mut parts = link.split(';');
+            let href = parts
+                .next()?
+                .trim()
+                .strip_prefix('<')?
+                .strip_suffix('>')?
+                .to_string();
+            let rel = parts.find_map(|param| {
+                let mut param = param.splitn(2, '=');
+                if param.next()?.trim().eq_ignore_ascii_case("rel") {
+                    Some(param.next()?.trim().trim_matches('"').to_string())
+                } else {
+                    None
+                }
+            })?;
+            Some((href, rel))
+        })
+        .collect()
+}
+
+/// Ask a hub to push updates for a feed, unless a subscription is already
+/// in place and not due for renewal. Without hub links from a fresh fetch,
+/// the hub and topic from any earlier subscription are used for renewal.
+/// Returns true if a subscription was requested.
+///
+/// # Errors
+///
+/// Will return `WebSubError` for any DB failure or if the hub refuses the request
+pub async fn renew_subscription(
+    db: &DbWriter,
+    feed_url: &str,
+    hub_links: Option<HubLinks>,
+    options: &WebSubOptions,
+    client: &reqwest::Client,
+    request_timeout: Duration,
+) -> Result<bool, WebSubError> {
+    let subscription_id = feed_id_from_url(feed_url);
+    let existing = {
+        let subscription_id = subscription_id.clone();
+        db.run(move |conn| find_websub_subscription(conn, &subscription_id))
+            .await
+            .map_err(WebSubError::DatabaseError)?
+    };
+    let hub_links = match (hub_links, &existing) {
+        (Some(hub_links), _) => hub_links,
+        (
+            None,
+            Some(models::WebSubSubscription {
+                hub_url: Some(hub_url),
+                topic_url: Some(topic_url),
+                ..
+            }),
+        ) => HubLinks {
+            hub_url: hub_url.clone(),
+            topic_url: topic_url.clone(),
+        },
+        _ => return Ok(false),
+    };
+    if let Some(existing) = &existing {
+        if !is_request_due(existing, &hub_links, Utc::now()) {
+            return Ok(false);
+        }
+    }
+
+    let secret = random_token();
+    // The callback URL is kept across renewals, since hubs identify a
+    // subscription by its topic and callback
+    let callback_token = existing
+        .and_then(|existing| existing.callback_token)
+        .filter(|callback_token| !callback_token.is_empty())
+        .unwrap_or_else(random_token);
+    let callback_url = format!(
+        "{}{}{}",
+        options.callback_base_url.trim_end_matches('/'),
+        CALLBACK_PATH,
+        callback_token
+    );
+    // Record the request first, since some hubs verify intent before responding
+    {
+        let (feed_url, hub_links, secret) =
+            (String::from(feed_url), hub_links.clone(), secret.clone());
+        db.run(move |conn| {
+            upsert_websub_request(
+                conn,
+                &models::WebSubSubscriptionRequest {
+                    feed_url: &feed_url,
+                    hub_url: &hub_links.hub_url,
+                    topic_url: &hub_links.topic_url,
+                    secret: &secret,
+                    state: STATE_PENDING,
+                    modified_at: &Utc::now().to_rfc3339(),
+                    callback_token: &callback_token,
+                },
+            )
+        })
+        .await
+        .map_err(WebSubError::DatabaseError)?;
+    }
+
+    let lease_seconds = options.lease_seconds.to_string();
+    let mut form = vec![
+        ("hub.mode", "subscribe"),
+        ("hub.topic", &hub_links.topic_url),
+        ("hub.callback", &callback_url),
+        ("hub.secret", &secret),
+    ];
+    if options.lease_seconds > 0 {
+        form.push(("hub.lease_seconds", &lease_seconds));
+    }
+    let response = client
+        .post(&hub_links.hub_url)
+        .timeout(request_timeout)
+        .form(&form)
+        .send()
+        .await
+        .map_err(WebSubError::RequestError)?;
+    if !response.status().is_success() {
+        return Err(WebSubError::HubRejected {
+            status: String::from(response.status().as_str()),
+            body: response.text().await.unwrap_or_default(),
+        });
+    }
+    log::info!(
+        "Requested WebSub subscription for {} from {}",
+        &hub_links.topic_url,
+        &hub_links.hub_url
+    );
+    Ok(true)
+}
+
+/// A random token, for a subscription secret or an unguessable callback URL
+fn random_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .collect()
+}
+
+fn is_request_due(
+    existing: &models::WebSubSubscription,
+    hub_links: &HubLinks,
+    now: DateTime<Utc>,
+) -> bool {
+    // Subscriptions from before callbacks had secret tokens are replaced
+    if existing.hub_url.as_ref() != Some(&hub_links.hub_url)
+        || existing.topic_url.as_ref() != Some(&hub_links.topic_url)
+        || existing.callback_token.is_none()
+    {
+        return true;
+    }
+    let parse_time = |time: &Option<String>| {
+        time.as_ref()
+            .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+            .map(|time| time.with_timezone(&Utc))
+    };
+    let modified_at = parse_time(&existing.modified_at);
+    match existing.state.as_deref() {
+        Some(STATE_VERIFIED) => parse_time(&existing.lease_expires_at).map_or(false, |expires| {
+            now + chrono::Duration::hours(LEASE_RENEWAL_MARGIN_HOURS) > expires
+        }),
+        Some(STATE_PENDING) => modified_at.map_or(true, |modified_at| {
+            now > modified_at + chrono::Duration::hours(PENDING_RETRY_HOURS)
+        }),
+        _ => modified_at.map_or(true, |modified_at| {
+            now > modified_at + chrono::Duration::hours(LEASE_RENEWAL_MARGIN_HOURS)
+        }),
+    }
+}
+
+/// Whether a hub has a live subscription to push updates for a feed
+#[must_use]
+pub fn is_push_active(conn: &SqliteConnection, feed_url: &str) -> bool {
+    match find_websub_subscription(conn, &feed_id_from_url(feed_url)) {
+        Ok(Some(models::WebSubSubscription {
+            state: Some(state),
+            lease_expires_at: Some(lease_expires_at),
+            ..
+        })) if state == STATE_VERIFIED => DateTime::parse_from_rfc3339(&lease_expires_at)
+            .map_or(false, |lease_expires_at| Utc::now() < lease_expires_at),
+        _ => false,
+    }
+}
+
+/// Find the subscription a callback URL belongs to, by its secret token
+fn find_callback_subscription(
+    conn: &SqliteConnection,
+    callback_token: &str,
+) -> Result<Option<models::WebSubSubscription>, WebSubError> {
+    if callback_token.is_empty() {
+        return Ok(None);
+    }
+    find_websub_subscription_by_callback(conn, callback_token).map_err(WebSubError::DatabaseError)
+}
+
+/// Handle a hub's verification of intent at a callback. Returns the
+/// challenge to echo back if the hub is confirming a subscription we asked
+/// for and are still waiting on, or None if the request should be refused.
+///
+/// # Errors
+///
+/// Will return `WebSubError` for any DB failure
+pub fn verify_intent(
+    conn: &SqliteConnection,
+    callback_token: &str,
+    params: &HashMap<String, String>,
+) -> Result<Option<String>, WebSubError> {
+    let subscription = match find_callback_subscription(conn, callback_token)? {
+        Some(subscription) => subscription,
+        None => return Ok(None),
+    };
+    let param = |name: &str| params.get(name).map(String::as_str);
+    if subscription.state.as_deref() != Some(STATE_PENDING)
+        || param("hub.topic") != subscription.topic_url.as_deref()
+    {
+        return Ok(None);
+    }
+    let subscription_id = subscription.id.unwrap_or_default();
+    match param("hub.mode") {
+        Some("subscribe") => {
+            let challenge = match param("hub.challenge") {
+                Some(challenge) => challenge,
+                None => return Ok(None),
+            };
+            let lease_expires_at = param("hub.lease_seconds")
+                .and_then(|lease_seconds| lease_seconds.parse::<i64>().ok())
+                .and_then(|lease_seconds| {
+                    Utc::now().checked_add_signed(chrono::Duration::seconds(
+                        lease_seconds.clamp(0, MAX_LEASE_SECONDS),
+                    ))
+                })
+                .map(|lease_expires_at| lease_expires_at.to_rfc3339());
+            update_websub_state(
+                conn,
+                &subscription_id,
+                STATE_VERIFIED,
+                lease_expires_at.as_deref(),
+            )
+            .map_err(WebSubError::DatabaseError)?;
+            log::info!(
+                "Verified WebSub subscription for {}",
+                subscription.topic_url.unwrap_or_default()
+            );
+            Ok(Some(String::from(challenge)))
+        }
+        Some("denied") => {
+            update_websub_state(conn, &subscription_id, STATE_DENIED, None)
+                .map_err(WebSubError::DatabaseError)?;
+            log::warn!(
+                "WebSub subscription denied for {} - {}",
+                subscription.topic_url.unwrap_or_default(),
+                param("hub.reason").unwrap_or("")
+            );
+            Ok(Some(String::from("")))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Ingest content pushed by a hub to a callback, updating the feed as if it
+/// had been polled, with the subscription's own overrides. Pushed content may hold only new entries, so entries
+/// missing from it are not marked defunct. Content without a valid signature
+/// is ignored and None returned, since the hub expects it acknowledged anyway.
+///
+/// # Errors
+///
+/// Will return `WebSubError::UnknownSubscription` if the callback doesn't
+/// belong to a subscription, or `WebSubError` for any parse or DB failure
+pub fn ingest_content(
+    conn: &SqliteConnection,
+    callback_token: &str,
+    headers: &reqwest::header::HeaderMap,
+    body: &[u8],
+    poll_options: &PollOptions,
+) -> Result<Option<FeedPollResult>, WebSubError> {
+    let subscription = find_callback_subscription(conn, callback_token)?
+        .ok_or(WebSubError::UnknownSubscription)?;
+    let subscription_id = subscription.id.unwrap_or_default();
+    let feed_url = subscription.feed_url.unwrap_or_default();
+    let overrides = find_subscription(conn, &feed_url)
+        .map_err(WebSubError::DatabaseError)?
+        .map_or_else(PollOverrides::default, |subscription| {
+            PollOverrides::from(&subscription)
+        });
+    let options = poll_options.with_overrides(&overrides);
+
+    if let Some(secret) = subscription.secret.filter(|secret| !secret.is_empty()) {
+        let signature = headers
+            .get("X-Hub-Signature")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("");
+        if !is_signature_valid(&secret, signature, body) {
+            log::warn!(
+                "Ignored WebSub content with bad signature for {}",
+                &feed_url
+            );
+            return Ok(None);
+        }
+    }
+
+    let content_type = headers
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok());
+    let (body, encoding) = decode_body(body, content_type);
+    let fetch = FeedFetchResult {
+        id: subscription_id,
+        url: feed_url.clone(),
+        final_url: feed_url,
+        moved_to: None,
+        status: String::from(reqwest::StatusCode::OK.as_str()),
+        headers: headers.clone(),
+        body,
+        encoding,
+    };
+    let feed = match fetch.parse_feed() {
+        Ok(feed) => feed,
+        Err(error) => {
+            return Err(WebSubError::PollError(FeedPollError::ParseError {
+                fetch,
+                error,
+            }))
+        }
+    };
+    let fetch_result = update_feed(
+        conn,
+        FeedPollResult::Fetched { fetch, feed },
+        options.skip_entry_update,
+        false,
+    )
+    .map_err(WebSubError::PollError)?;
+    if let FeedPollResult::Updated { fetch, .. } = &fetch_result {
+        insert_feed_history(conn, fetch, options.retain_src, None)
+            .map_err(WebSubError::PollError)?;
+    }
+    Ok(Some(fetch_result))
+}
+
+/// Check an `X-Hub-Signature` header of the form `method=hexdigest`
+fn is_signature_valid(secret: &str, signature: &str, body: &[u8]) -> bool {
+    let mut parts = signature.trim().splitn(2, '=');
+    let method = parts.next().unwrap_or("");
+    let code = match parts.next().and_then(decode_hex) {
+        Some(code) => code,
+        None => return false,
+    };
+    match method {
+        "sha1" => is_hmac_valid::<Sha1>(secret, body, &code),
+        "sha256" => is_hmac_valid::<Sha256>(secret, body, &code),
+        "sha384" => is_hmac_valid::<Sha384>(secret, body, &code),
+        "sha512" => is_hmac_valid::<Sha512>(secret, body, &code),
+        _ => false,
+    }
+}
+
+fn is_hmac_valid<D>(secret: &str, body: &[u8], code: &[u8]) -> bool
+where
+    Hmac<D>: Mac + NewMac,
+{
+    Hmac::<D>::new_varkey(secret.as_bytes()).map_or(false, |mut mac| {
+        mac.update(body);
+        mac.verify(code).is_ok()
+    })
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(hex.get(idx..idx + 2)?, 16).ok())
+        .collect()
+}